@@ -0,0 +1,148 @@
+use std::io;
+
+use tokio_i3ipc::{
+    event::{Event, WindowChange, WorkspaceChange},
+    reply::Node,
+    I3,
+};
+
+/// A copy of the i3 tree that the listener keeps current from i3 events, so actions don't have
+/// to fetch the whole tree on every keypress.
+///
+/// Events that only change focus or a window's title, marks or urgency are applied to the copy
+/// in place. Anything that changes the shape of the tree, or an event about a container the copy
+/// doesn't know about, makes the copy stale and it is fetched again the next time it is needed.
+#[derive(Default)]
+pub struct TreeCache {
+    tree: Option<Node>,
+}
+
+impl TreeCache {
+    /// Get a copy of the tree, fetching it from i3 first if the cached one is stale
+    pub async fn get(&mut self, conn: &mut I3) -> io::Result<Node> {
+        let tree = match self.tree {
+            Some(ref tree) => {
+                log::trace!("Tree cache hit");
+                tree
+            }
+            None => {
+                log::debug!("Tree cache stale, refreshing");
+                self.tree.insert(conn.get_tree().await?)
+            }
+        };
+        Ok(tree.clone())
+    }
+
+    /// Throw away the cached tree
+    pub fn invalidate(&mut self) {
+        self.tree = None;
+    }
+
+    /// Update the cached tree from an i3 event
+    pub fn apply(&mut self, event: &Event) {
+        let Some(ref mut tree) = self.tree else {
+            return;
+        };
+
+        let current = match event {
+            Event::Window(ev) => match ev.change {
+                WindowChange::Focus => set_focus(tree, ev.container.id),
+                WindowChange::Title | WindowChange::Mark | WindowChange::Urgent => {
+                    update_window(tree, &ev.container)
+                }
+                _ => false,
+            },
+            Event::Workspace(ev) => match (ev.change, &ev.current) {
+                (WorkspaceChange::Focus, Some(ws)) => set_focus(tree, ws.id),
+                (WorkspaceChange::Urgent, Some(ws)) => find_mut(tree, ws.id)
+                    .map(|n| n.urgent = ws.urgent)
+                    .is_some(),
+                _ => false,
+            },
+            Event::Output(_) => false,
+            _ => true,
+        };
+
+        if !current {
+            log::debug!("Tree cache invalidated by event");
+            self.invalidate();
+        }
+    }
+}
+
+fn find_mut(n: &mut Node, id: usize) -> Option<&mut Node> {
+    if n.id == id {
+        return Some(n);
+    }
+    n.nodes
+        .iter_mut()
+        .chain(n.floating_nodes.iter_mut())
+        .find_map(|c| find_mut(c, id))
+}
+
+/// Copy the window level fields of an event container onto the cached node
+fn update_window(tree: &mut Node, container: &Node) -> bool {
+    match find_mut(tree, container.id) {
+        Some(n) => {
+            n.name = container.name.clone();
+            n.window_properties = container.window_properties.clone();
+            n.marks = container.marks.clone();
+            n.urgent = container.urgent;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Move focus to the node with `id` the way i3 does: each container on the path to it puts the
+/// path first in its focus stack, and focus lands on the most recently focused leaf under it.
+fn set_focus(tree: &mut Node, id: usize) -> bool {
+    if find_mut(tree, id).is_none() {
+        return false;
+    }
+    clear_focus(tree);
+    focus_path(tree, id);
+    true
+}
+
+fn clear_focus(n: &mut Node) {
+    n.focused = false;
+    for c in n.nodes.iter_mut().chain(n.floating_nodes.iter_mut()) {
+        clear_focus(c);
+    }
+}
+
+fn focus_path(n: &mut Node, id: usize) -> bool {
+    if n.id == id {
+        focus_last_leaf(n);
+        return true;
+    }
+
+    let next = n
+        .nodes
+        .iter_mut()
+        .chain(n.floating_nodes.iter_mut())
+        .find_map(|c| focus_path(c, id).then_some(c.id));
+
+    match next {
+        Some(child) => {
+            n.focus.retain(|f| *f != child);
+            n.focus.insert(0, child);
+            true
+        }
+        None => false,
+    }
+}
+
+fn focus_last_leaf(n: &mut Node) {
+    let next = n.focus.first().copied();
+    match n
+        .nodes
+        .iter_mut()
+        .chain(n.floating_nodes.iter_mut())
+        .find(|c| Some(c.id) == next)
+    {
+        Some(c) => focus_last_leaf(c),
+        None => n.focused = true,
+    }
+}
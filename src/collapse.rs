@@ -1,12 +1,33 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use tokio_i3ipc::reply::Node;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio_i3ipc::reply::{Node, NodeLayout, NodeType};
 
 use crate::ext::{NodeSearch, NotFound};
 
-fn find_candidate(root: &Node) -> Vec<(&Node, usize)> {
+/// The ways `fix` tidies up a workspace
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pass {
+    /// move containers up out of chains of containers that only hold one thing
+    Chains,
+    /// merge splits into a parent split with the same layout, keeping their order and sizes
+    Flatten,
+}
+
+/// A container `fix` would move up, out of the containers wrapped around it
+pub struct Candidate<'a> {
+    /// The container that gets moved
+    pub leaf: &'a Node,
+    /// Where it gets moved to
+    pub parent: &'a Node,
+    /// The containers between the two, each with only one child
+    pub chain: Vec<&'a Node>,
+}
+
+pub fn find_candidate(root: &Node) -> Vec<Candidate<'_>> {
     let mut leaves_seen = HashSet::new();
-    let mut res: Vec<(&Node, usize)> = Vec::with_capacity(2);
+    let mut res: Vec<Candidate> = Vec::with_capacity(2);
     for s in root.preorder() {
         log::debug!("Walk to: id({})", s.n.id);
         // skip root since it's a workspace and that gets messy
@@ -17,7 +38,9 @@ fn find_candidate(root: &Node) -> Vec<(&Node, usize)> {
 
         for child in s.n.nodes.iter() {
             let mut n = child;
+            let mut chain = Vec::new();
             while n.nodes.len() == 1 {
+                chain.push(n);
                 n = &n.nodes[0];
             }
 
@@ -27,23 +50,744 @@ fn find_candidate(root: &Node) -> Vec<(&Node, usize)> {
             // again, since it's an ignored command issuee otherwise
             if n.id != child.id && !leaves_seen.contains(&n.id) {
                 leaves_seen.insert(n.id);
-                res.push((n, s.n.id));
+                res.push(Candidate {
+                    leaf: n,
+                    parent: s.n,
+                    chain,
+                });
             }
         }
     }
     res
 }
 
-pub fn clean_current_workspace(tree: &Node) -> Result<Vec<String>, NotFound> {
-    let ws = tree.get_current_workspace()?;
+/// The workspaces `fix` cleans up
+pub enum Scope<'a> {
+    Current,
+    All,
+    Workspace(&'a str),
+    Output(&'a str),
+}
+
+fn find_workspaces<'a>(tree: &'a Node, scope: &Scope) -> Result<Vec<&'a Node>, NotFound> {
+    Ok(match scope {
+        Scope::Current => vec![tree.get_current_workspace()?],
+        // windows in the scratchpad are all floating, so there's nothing to fix there
+        Scope::All => tree
+            .workspaces()
+            .into_iter()
+            .filter(|ws| ws.name.as_deref() != Some("__i3_scratch"))
+            .collect(),
+        Scope::Workspace(name) => vec![tree
+            .workspaces()
+            .into_iter()
+            .find(|ws| ws.name.as_deref() == Some(*name))
+            .ok_or_else(|| NotFound::NamedWorkspace(name.to_string()))?],
+        Scope::Output(name) => {
+            let output = tree
+                .nodes
+                .iter()
+                .find(|o| o.node_type == NodeType::Output && o.name.as_deref() == Some(*name))
+                .ok_or_else(|| NotFound::NamedOutput(name.to_string()))?;
+            output.workspaces()
+        }
+    })
+}
+
+/// What `fix` does to a workspace
+pub struct Plan<'a> {
+    /// The containers the chains pass moves up
+    pub chains: Vec<Candidate<'a>>,
+    /// The splits the flatten pass looks at, and what it does with each one
+    pub merges: Vec<Merge<'a>>,
+}
+
+/// A split inside a split with the same layout
+pub struct Merge<'a> {
+    pub split: &'a Node,
+    pub parent: &'a Node,
+    /// The commands that merge it into the container around it, or why it's left alone
+    pub outcome: Result<String, Kept>,
+}
+
+/// Why the flatten pass leaves a split alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kept {
+    /// The chains pass moves it, or moves something into it, so it's left for the next fix
+    Chains,
+    /// There's no container without children next to it to line its children up against
+    NoAnchor,
+}
 
+/// Work out what `fix` does to a workspace, using the given passes, or all of them if none are
+/// given.
+pub fn plan<'a>(ws: &'a Node, passes: &[Pass]) -> Plan<'a> {
+    let run = |pass| passes.is_empty() || passes.contains(&pass);
+    let chains = match run(Pass::Chains) {
+        true => find_candidate(ws),
+        false => Vec::new(),
+    };
+    let merges = match run(Pass::Flatten) {
+        true => {
+            // The commands all work out of the same tree, so flattening a split the chains pass
+            // just moved, or moved things into, would go wrong. Those are left for the next fix.
+            let touched: HashSet<usize> = chains
+                .iter()
+                .flat_map(|c| c.chain.iter().chain([&c.leaf, &c.parent]))
+                .map(|n| n.id)
+                .collect();
+            flatten(ws, &touched)
+        }
+        false => Vec::new(),
+    };
+    Plan { chains, merges }
+}
+
+impl Plan<'_> {
+    /// The commands to carry out the plan. Containers are picked out by id rather than focused,
+    /// so the focus stays where it is.
+    pub fn commands(&self) -> Vec<String> {
+        let mut res = collapse_chains(&self.chains);
+        res.extend(self.merges.iter().filter_map(|m| m.outcome.clone().ok()));
+        res
+    }
+}
+
+/// The commands to collapse the nesting on the workspaces in scope, using the given passes, or
+/// all of them if none are given.
+pub fn clean(tree: &Node, scope: Scope, passes: &[Pass]) -> Result<Vec<String>, NotFound> {
+    Ok(find_workspaces(tree, &scope)?
+        .into_iter()
+        .flat_map(|ws| plan(ws, passes).commands())
+        .collect())
+}
+
+fn collapse_chains(candidates: &[Candidate]) -> Vec<String> {
     let mut res = Vec::new();
-    for (candidate, to) in find_candidate(ws) {
+    for candidate in candidates {
         let cmd = format!(
             "[con_id={}] mark i3v; [con_id={}] move container to mark i3v; unmark i3v",
-            to, candidate.id
+            candidate.parent.id, candidate.leaf.id
         );
         res.push(cmd)
     }
-    Ok(res)
+    res
+}
+
+/// The sizes of a container's children, as shares of it that add up to 1, the way i3 keeps them
+type Sizes<'a> = Vec<(&'a Node, f64)>;
+
+fn sizes(n: &Node) -> Sizes<'_> {
+    let mut res = n
+        .nodes
+        .iter()
+        .map(|c| (c, c.percent.unwrap_or(0.0)))
+        .collect();
+    fix_percent(&mut res);
+    res
+}
+
+/// What i3 does to the sizes when a container's children change: children without a size get the
+/// average of the ones before, and then they are all scaled to add up to 1.
+fn fix_percent(sizes: &mut Sizes) {
+    let mut total: f64 = sizes.iter().map(|s| s.1).filter(|p| *p > 0.0).sum();
+    let with_size = sizes.iter().filter(|s| s.1 > 0.0).count();
+    for s in sizes.iter_mut().filter(|s| s.1 <= 0.0) {
+        s.1 = match with_size {
+            0 => 1.0,
+            n => total / n as f64,
+        };
+        total += s.1;
+    }
+    for s in sizes.iter_mut() {
+        s.1 /= total;
+    }
+}
+
+/// What `move container to mark` does to the sizes when it puts `node` after `anchor`
+fn move_after<'a>(sizes: &mut Sizes<'a>, node: &'a Node, anchor: usize) {
+    if let Some(i) = sizes.iter().position(|s| s.0.id == node.id) {
+        sizes.remove(i);
+        fix_percent(sizes);
+    }
+    let pos = sizes
+        .iter()
+        .position(|s| s.0.id == anchor)
+        .map_or(sizes.len(), |i| i + 1);
+    sizes.insert(pos, (node, 0.0));
+    fix_percent(sizes);
+}
+
+/// What `resize set` does to the sizes: the child gets the new size, and the difference is taken
+/// evenly from all the others.
+fn resize_set(sizes: &mut Sizes, i: usize, size: f64) {
+    let diff = (size - sizes[i].1) / (sizes.len() - 1) as f64;
+    for (j, s) in sizes.iter_mut().enumerate() {
+        s.1 = match j == i {
+            true => size,
+            false => s.1 - diff,
+        };
+    }
+}
+
+/// The `resize set` commands that take a container's children from `sizes` to `target`, leaving
+/// `sizes` as they end up. Since each one moves all the other children too, the size asked for
+/// each is worked out to land on the target once all of them have run. The last child gets what's
+/// left.
+fn resize_steps(sizes: &mut Sizes, target: &[f64], dimension: &str) -> Vec<String> {
+    let n = sizes.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let last = sizes[n - 1].1 - target[n - 1];
+    let diffs: Vec<f64> = (0..n - 1)
+        .map(|k| (n - 1) as f64 / n as f64 * (target[k] - sizes[k].1 + last))
+        .collect();
+    let mut res = Vec::new();
+    for (k, diff) in diffs.into_iter().enumerate() {
+        // i3 only takes whole percentages
+        let ppt = ((sizes[k].1 + diff) * 100.0).round();
+        res.push(format!(
+            "[con_id={}] resize set {} {} ppt",
+            sizes[k].0.id, dimension, ppt
+        ));
+        resize_set(sizes, k, ppt / 100.0);
+    }
+    res
+}
+
+/// The commands to move a split's children out into the container around it, where the split is,
+/// and size them so that they share out the split's size and everything else keeps its size.
+/// `around` holds the sizes in the container around it, and is left with the sizes after the
+/// merge. i3 removes the split once it is empty.
+///
+/// `move container to mark` puts a container just after the marked one, in the marked one's
+/// parent. A mark on a container with children is taken to mean its focused child instead, so
+/// only childless containers work as anchors. The children go after the container before the
+/// split, last first, which leaves them in order. Failing that, they go after the container after
+/// the split, which is then moved to the end, after the last of them.
+fn merge<'a>(split: &'a Node, inside: &Sizes<'a>, around: &mut Sizes<'a>) -> Result<String, Kept> {
+    let pos = around
+        .iter()
+        .position(|s| s.0.id == split.id)
+        .ok_or(Kept::NoAnchor)?;
+    let childless = |i: usize| around.get(i).map(|s| s.0).filter(|n| n.nodes.is_empty());
+    let before = pos.checked_sub(1).and_then(childless);
+    let after = childless(pos + 1);
+    let last = inside.last().ok_or(Kept::NoAnchor)?.0;
+    let (anchor, tail) = match (before, after) {
+        (Some(before), _) => (before, None),
+        (None, Some(after)) if last.nodes.is_empty() => (after, Some(after)),
+        _ => return Err(Kept::NoAnchor),
+    };
+
+    let share = around[pos].1;
+    let mut target: HashMap<usize, f64> = around.iter().map(|s| (s.0.id, s.1)).collect();
+    target.extend(inside.iter().map(|s| (s.0.id, s.1 * share)));
+
+    let mut cmds = vec![format!("[con_id={}] mark --add i3v", anchor.id)];
+    for (child, _) in inside.iter().rev() {
+        cmds.push(format!("[con_id={}] move container to mark i3v", child.id));
+        move_after(around, child, anchor.id);
+    }
+    around.retain(|s| s.0.id != split.id);
+    fix_percent(around);
+    if let Some(tail) = tail {
+        cmds.push(format!("[con_id={}] mark --add i3v", last.id));
+        cmds.push(format!("[con_id={}] move container to mark i3v", tail.id));
+        move_after(around, tail, last.id);
+    }
+    cmds.push("unmark i3v".to_string());
+
+    let dimension = match split.layout {
+        NodeLayout::SplitV => "height",
+        _ => "width",
+    };
+    let target: Vec<f64> = around.iter().map(|s| target[&s.0.id]).collect();
+    cmds.extend(resize_steps(around, &target, dimension));
+    Ok(cmds.join("; "))
+}
+
+/// Merge each split into a parent with the same layout, skipping the ones in `skip`. Later
+/// merges work from the sizes and places the earlier ones leave behind, so a split inside a split
+/// that is merged too ends up sized in the container both are merged into.
+fn flatten<'a>(ws: &'a Node, skip: &HashSet<usize>) -> Vec<Merge<'a>> {
+    // the children of each container the merges so far have changed
+    let mut layouts: HashMap<usize, Sizes> = HashMap::new();
+    // the container each merged split's children went to
+    let mut merged: HashMap<usize, usize> = HashMap::new();
+    let mut res = Vec::new();
+    for (split, parent) in same_orientation(ws) {
+        let outcome = match skip.contains(&split.id) {
+            true => Err(Kept::Chains),
+            false => {
+                let mut into = parent.id;
+                while let Some(next) = merged.get(&into) {
+                    into = *next;
+                }
+                let inside = sizes(split);
+                let around = layouts.entry(into).or_insert_with(|| sizes(parent));
+                let outcome = merge(split, &inside, around);
+                if outcome.is_ok() {
+                    merged.insert(split.id, into);
+                }
+                outcome
+            }
+        };
+        res.push(Merge {
+            split,
+            parent,
+            outcome,
+        });
+    }
+    res
+}
+
+/// Split containers inside a split container with the same layout. They split the same way as
+/// their parent, so the nesting only makes moving windows around harder. Returns each one with
+/// its parent.
+pub fn same_orientation(root: &Node) -> Vec<(&Node, &Node)> {
+    let mut res = Vec::new();
+    for s in root.preorder() {
+        if !matches!(s.n.layout, NodeLayout::SplitH | NodeLayout::SplitV) {
+            continue;
+        }
+        for child in s.n.nodes.iter() {
+            // a container with one child is a chain, which find_candidate already covers
+            if child.nodes.len() > 1 && child.layout == s.n.layout {
+                res.push((child, s.n));
+            }
+        }
+    }
+    res
+}
+
+/// Containers with no windows and nothing in them, which i3 usually cleans up by itself
+pub fn empty(root: &Node) -> Vec<&Node> {
+    root.preorder()
+        .map(|s| s.n)
+        .filter(|n| {
+            n.node_type == NodeType::Con
+                && n.window.is_none()
+                && n.nodes.is_empty()
+                && n.floating_nodes.is_empty()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{con, output, percent, root, win, workspace};
+
+    #[test]
+    fn chain_leaf_moves_to_the_top_of_the_chain() {
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![con(
+                20,
+                "splith",
+                vec![
+                    win(1),
+                    con(21, "splitv", vec![con(22, "splith", vec![win(2)])]),
+                ],
+            )],
+        );
+        let found = find_candidate(&ws);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].leaf.id, 2);
+        assert_eq!(found[0].parent.id, 20);
+        let chain: Vec<usize> = found[0].chain.iter().map(|n| n.id).collect();
+        assert_eq!(chain, [21, 22]);
+    }
+
+    #[test]
+    fn chains_right_under_the_workspace_are_left_alone() {
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![win(1), con(20, "splitv", vec![win(2)])],
+        );
+        assert!(find_candidate(&ws).is_empty());
+    }
+
+    #[test]
+    fn same_orientation_needs_a_split_with_the_same_layout() {
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![
+                con(20, "splith", vec![win(1), win(2)]),
+                con(21, "splitv", vec![win(3), win(4)]),
+                // a chain, not a split
+                con(22, "splith", vec![win(5)]),
+                con(23, "tabbed", vec![con(24, "tabbed", vec![win(6), win(7)])]),
+            ],
+        );
+        let found: Vec<(usize, usize)> = same_orientation(&ws)
+            .iter()
+            .map(|(n, parent)| (n.id, parent.id))
+            .collect();
+        assert_eq!(found, [(20, 10)]);
+    }
+
+    /// Run commands on a model of the tree under `ws`, the way i3 would, and return the children
+    /// of each container with their sizes
+    fn run(ws: &Node, cmds: &[String]) -> HashMap<usize, Vec<(usize, f64)>> {
+        let nodes: HashMap<usize, &Node> =
+            ws.all_containers().into_iter().map(|n| (n.id, n)).collect();
+        let mut layouts: HashMap<usize, Sizes> = nodes
+            .values()
+            .filter(|n| !n.nodes.is_empty())
+            .map(|n| (n.id, sizes(n)))
+            .collect();
+        let parent_of = |layouts: &HashMap<usize, Sizes>, id: usize| {
+            *layouts
+                .iter()
+                .find(|(_, sizes)| sizes.iter().any(|s| s.0.id == id))
+                .expect("container in the tree")
+                .0
+        };
+        let con = |cmd: &str| -> usize {
+            let id = cmd
+                .strip_prefix("[con_id=")
+                .unwrap()
+                .split(']')
+                .next()
+                .unwrap();
+            id.parse().unwrap()
+        };
+        let mut mark = None;
+        for cmd in cmds.iter().flat_map(|c| c.split("; ")) {
+            if cmd.ends_with("] mark --add i3v") {
+                mark = Some(con(cmd));
+            } else if cmd.ends_with("] move container to mark i3v") {
+                let (id, anchor) = (con(cmd), mark.unwrap());
+                // a mark on a container with children would move it inside that container
+                assert!(nodes[&anchor].nodes.is_empty());
+                let from = parent_of(&layouts, id);
+                let sizes = layouts.get_mut(&from).unwrap();
+                sizes.retain(|s| s.0.id != id);
+                fix_percent(sizes);
+                if sizes.is_empty() {
+                    // i3 closes the emptied container
+                    layouts.remove(&from);
+                    let up = parent_of(&layouts, from);
+                    let sizes = layouts.get_mut(&up).unwrap();
+                    sizes.retain(|s| s.0.id != from);
+                    fix_percent(sizes);
+                }
+                let to = parent_of(&layouts, anchor);
+                move_after(layouts.get_mut(&to).unwrap(), nodes[&id], anchor);
+            } else if let Some((_, size)) = cmd.split_once(" resize set ") {
+                let id = con(cmd);
+                let ppt: f64 = size.split(' ').nth(1).unwrap().parse().unwrap();
+                let sizes = layouts.get_mut(&parent_of(&layouts, id)).unwrap();
+                let i = sizes.iter().position(|s| s.0.id == id).unwrap();
+                resize_set(sizes, i, ppt / 100.0);
+            } else {
+                assert_eq!(cmd, "unmark i3v");
+            }
+        }
+        layouts
+            .into_iter()
+            .map(|(id, sizes)| (id, sizes.iter().map(|s| (s.0.id, s.1)).collect()))
+            .collect()
+    }
+
+    /// Check the children of a container and their sizes, to within what whole percentages allow
+    fn assert_sizes(
+        layouts: &HashMap<usize, Vec<(usize, f64)>>,
+        id: usize,
+        expected: &[(usize, f64)],
+    ) {
+        let got = &layouts[&id];
+        let ids: Vec<usize> = got.iter().map(|s| s.0).collect();
+        let expected_ids: Vec<usize> = expected.iter().map(|s| s.0).collect();
+        assert_eq!(ids, expected_ids);
+        for ((id, got), (_, expected)) in got.iter().zip(expected) {
+            assert!(
+                (got - expected).abs() < 0.01,
+                "container {} is {} rather than {}",
+                id,
+                got,
+                expected
+            );
+        }
+    }
+
+    /// The commands the flatten pass sends for a workspace
+    fn flatten_commands(ws: &Node, skip: &HashSet<usize>) -> Vec<String> {
+        flatten(ws, skip)
+            .into_iter()
+            .filter_map(|m| m.outcome.ok())
+            .collect()
+    }
+
+    #[test]
+    fn flatten_anchors_on_the_window_before_the_split() {
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![
+                percent(win(1), 0.4),
+                percent(
+                    con(
+                        20,
+                        "splith",
+                        vec![percent(win(2), 0.25), percent(win(3), 0.75)],
+                    ),
+                    0.4,
+                ),
+                percent(win(4), 0.2),
+            ],
+        );
+        let cmds = flatten_commands(&ws, &HashSet::new());
+        assert_eq!(cmds.len(), 1);
+        assert!(cmds[0].starts_with(
+            "[con_id=1] mark --add i3v; \
+             [con_id=3] move container to mark i3v; \
+             [con_id=2] move container to mark i3v; \
+             unmark i3v; "
+        ));
+        // the windows that were already there keep their sizes
+        assert_sizes(
+            &run(&ws, &cmds),
+            10,
+            &[(1, 0.4), (2, 0.1), (3, 0.3), (4, 0.2)],
+        );
+    }
+
+    #[test]
+    fn flatten_anchors_on_the_window_after_a_leading_split() {
+        let ws = workspace(
+            10,
+            "1",
+            "splitv",
+            vec![
+                percent(
+                    con(
+                        20,
+                        "splitv",
+                        vec![percent(win(2), 0.5), percent(win(3), 0.5)],
+                    ),
+                    0.6,
+                ),
+                percent(win(1), 0.4),
+            ],
+        );
+        let cmds = flatten_commands(&ws, &HashSet::new());
+        assert_eq!(cmds.len(), 1);
+        assert!(cmds[0].starts_with(
+            "[con_id=1] mark --add i3v; \
+             [con_id=3] move container to mark i3v; \
+             [con_id=2] move container to mark i3v; \
+             [con_id=3] mark --add i3v; \
+             [con_id=1] move container to mark i3v; \
+             unmark i3v; \
+             [con_id=2] resize set height "
+        ));
+        assert_sizes(&run(&ws, &cmds), 10, &[(2, 0.3), (3, 0.3), (1, 0.4)]);
+    }
+
+    #[test]
+    fn flatten_leaves_splits_without_an_anchor() {
+        let split = |id, first| con(id, "splith", vec![win(first), win(first + 1)]);
+        // nothing but splits beside it
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![split(20, 1), split(21, 3), split(22, 5)],
+        );
+        let merges = flatten(&ws, &HashSet::new());
+        assert_eq!(merges.len(), 3);
+        assert!(merges.iter().all(|m| m.outcome == Err(Kept::NoAnchor)));
+        // the window after it can't be moved behind a split
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![
+                con(
+                    20,
+                    "splith",
+                    vec![win(1), con(21, "splitv", vec![win(2), win(3)])],
+                ),
+                win(4),
+            ],
+        );
+        let merges = flatten(&ws, &HashSet::new());
+        assert_eq!(merges.len(), 1);
+        assert_eq!(merges[0].outcome, Err(Kept::NoAnchor));
+    }
+
+    /// A splith holding window 1 and a split of window 2 and another split of windows 3 and 4,
+    /// each taking half of the one it's in
+    fn nested() -> Node {
+        let half = |n| percent(n, 0.5);
+        workspace(
+            10,
+            "1",
+            "splith",
+            vec![
+                half(win(1)),
+                half(con(
+                    20,
+                    "splith",
+                    vec![
+                        half(win(2)),
+                        half(con(21, "splith", vec![half(win(3)), half(win(4))])),
+                    ],
+                )),
+            ],
+        )
+    }
+
+    #[test]
+    fn flatten_nested_splits_share_the_outer_size() {
+        let ws = nested();
+        let cmds = flatten_commands(&ws, &HashSet::new());
+        assert_eq!(cmds.len(), 2);
+        assert!(cmds[1].starts_with("[con_id=2] mark --add i3v"));
+        assert_sizes(
+            &run(&ws, &cmds),
+            10,
+            &[(1, 0.5), (2, 0.25), (3, 0.125), (4, 0.125)],
+        );
+    }
+
+    #[test]
+    fn flatten_skipped_split_keeps_what_is_merged_into_it() {
+        let ws = nested();
+        let merges = flatten(&ws, &HashSet::from([20]));
+        assert_eq!(merges[0].outcome, Err(Kept::Chains));
+        let cmds: Vec<String> = merges.into_iter().filter_map(|m| m.outcome.ok()).collect();
+        assert_eq!(cmds.len(), 1);
+        let layouts = run(&ws, &cmds);
+        assert_sizes(&layouts, 10, &[(1, 0.5), (20, 0.5)]);
+        assert_sizes(&layouts, 20, &[(2, 0.5), (3, 0.25), (4, 0.25)]);
+    }
+
+    /// Two outputs and the scratchpad. Workspace 1 is focused and has a chain to collapse and a
+    /// split to flatten, workspaces 2 and 3 have a split each and the scratchpad has a chain.
+    fn tree() -> Node {
+        let ws1 = workspace(
+            10,
+            "1",
+            "splith",
+            vec![
+                con(
+                    20,
+                    "splitv",
+                    vec![
+                        win(12),
+                        con(21, "splith", vec![con(22, "splitv", vec![win(13)])]),
+                    ],
+                ),
+                win(11),
+                con(23, "splith", vec![win(14), win(15)]),
+            ],
+        );
+        let ws2 = workspace(
+            30,
+            "2",
+            "splith",
+            vec![con(31, "splith", vec![win(21), win(22)]), win(23)],
+        );
+        let ws3 = workspace(
+            40,
+            "3",
+            "splitv",
+            vec![win(31), con(41, "splitv", vec![win(32), win(33)])],
+        );
+        let scratch = workspace(
+            50,
+            "__i3_scratch",
+            "splith",
+            vec![con(
+                51,
+                "splith",
+                vec![
+                    win(41),
+                    con(52, "splitv", vec![con(53, "splith", vec![win(42)])]),
+                ],
+            )],
+        );
+        root(vec![
+            output(100, "eDP-1", vec![ws1, ws2]),
+            output(200, "HDMI-1", vec![ws3]),
+            output(300, "__i3", vec![scratch]),
+        ])
+    }
+
+    #[test]
+    fn clean_runs_the_passes_asked_for() {
+        let tree = tree();
+        let chains = clean(&tree, Scope::Current, &[Pass::Chains]).unwrap();
+        assert_eq!(
+            chains,
+            ["[con_id=20] mark i3v; [con_id=13] move container to mark i3v; unmark i3v"]
+        );
+        let flat = clean(&tree, Scope::Current, &[Pass::Flatten]).unwrap();
+        assert_eq!(flat.len(), 1);
+        assert!(flat[0].starts_with("[con_id=11] mark --add i3v"));
+        assert_eq!(
+            clean(&tree, Scope::Current, &[]).unwrap(),
+            [&chains[..], &flat[..]].concat()
+        );
+    }
+
+    #[test]
+    fn clean_leaves_splits_the_chains_pass_touches() {
+        // the chain's leaf moves into the split that could be flattened
+        let ws = workspace(
+            10,
+            "1",
+            "splith",
+            vec![
+                win(1),
+                con(
+                    20,
+                    "splith",
+                    vec![
+                        win(2),
+                        con(21, "splitv", vec![con(22, "splith", vec![win(3)])]),
+                    ],
+                ),
+            ],
+        );
+        let plan = plan(&ws, &[]);
+        assert_eq!(plan.merges.len(), 1);
+        assert_eq!(plan.merges[0].outcome, Err(Kept::Chains));
+        let tree = root(vec![output(100, "eDP-1", vec![ws])]);
+        assert_eq!(clean(&tree, Scope::Current, &[]).unwrap().len(), 1);
+        let flat = clean(&tree, Scope::Current, &[Pass::Flatten]).unwrap();
+        assert_eq!(flat.len(), 1);
+        assert!(flat[0].starts_with("[con_id=1] mark --add i3v"));
+    }
+
+    #[test]
+    fn clean_covers_the_workspaces_in_scope() {
+        let tree = tree();
+        let count = |scope| clean(&tree, scope, &[]).map(|cmds| cmds.len());
+        // the scratchpad is left out
+        assert_eq!(count(Scope::All).unwrap(), 4);
+        assert_eq!(count(Scope::Workspace("2")).unwrap(), 1);
+        assert_eq!(count(Scope::Output("eDP-1")).unwrap(), 3);
+        assert_eq!(count(Scope::Output("HDMI-1")).unwrap(), 1);
+        assert!(matches!(
+            count(Scope::Workspace("9")),
+            Err(NotFound::NamedWorkspace(name)) if name == "9"
+        ));
+        assert!(matches!(
+            count(Scope::Output("DP-2")),
+            Err(NotFound::NamedOutput(name)) if name == "DP-2"
+        ));
+    }
 }
@@ -0,0 +1,336 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{CommandFactory, Parser, ValueEnum};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    ext::split_words, floats::Positioning, macros::Macros, parse_action_string, rules::Rules,
+    Action, Cmd, ReceivedCmd,
+};
+
+/// How deep actions can be nested in `when` and `macro --step` before `check` gives up, which
+/// only an alias that ends up running itself should reach
+const MAX_NESTING: usize = 8;
+
+/// The settings in `i3-valet.toml`:
+///
+/// ```toml
+/// [defaults]
+/// positioning = "abs"
+///
+/// [alias]
+/// corner = "loc rel se"
+///
+/// [macro]
+/// new-main = ["nop workspace move-new", "nop layout main set"]
+///
+/// [[rule]]
+/// class = "^Pavucontrol$"
+/// run = ["floating enable", "nop corner"]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// Names for actions, which can be used in place of them. Any arguments given after the
+    /// alias are added to the end of the action.
+    #[serde(default)]
+    alias: BTreeMap<String, String>,
+    #[serde(default, rename = "macro")]
+    pub macros: Macros,
+    #[serde(default, rename = "rule")]
+    pub rules: Rules,
+}
+
+/// Values used when an action isn't told otherwise
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct Defaults {
+    /// how `loc` positions windows when the positioning is left out
+    #[serde(deserialize_with = "value_enum")]
+    pub positioning: Positioning,
+    /// the names of the marks the `layout` actions set, with `{workspace}` and `{name}` filled in
+    pub mark_format: String,
+    /// the lowest numbered workspace the `workspace` actions will use
+    pub first_workspace: i32,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Defaults {
+            positioning: Positioning::Relative,
+            mark_format: "{workspace}_{name}".into(),
+            first_workspace: 1,
+        }
+    }
+}
+
+/// Read a value the way it is spelled on the command line
+fn value_enum<'de, D: Deserializer<'de>, T: ValueEnum>(d: D) -> Result<T, D::Error> {
+    let s = String::deserialize(d)?;
+    T::from_str(&s, false).map_err(de::Error::custom)
+}
+
+/// Where the config is read from when `--config` isn't given: `$XDG_CONFIG_HOME`, or `~/.config`
+/// when that isn't set.
+pub fn config_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("i3-valet.toml"))
+}
+
+impl Config {
+    /// Load the config from `path`, or from [`config_path`] if that isn't given. It is only an
+    /// error for the default config to be missing if it was asked for by name.
+    pub fn find(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => Config::load(path),
+            None => match config_path() {
+                Some(path) if path.exists() => Config::load(&path),
+                _ => Ok(Config::default()),
+            },
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading config from {}", path.display()))?;
+        let config: Config = toml::from_str(&text)
+            .map_err(anyhow::Error::from)
+            .and_then(Config::validate)
+            .with_context(|| format!("Parsing config from {}", path.display()))?;
+        log::debug!("Loaded config from {}", path.display());
+        Ok(config)
+    }
+
+    fn validate(mut self) -> anyhow::Result<Self> {
+        let d = &self.defaults;
+        if !d.mark_format.contains("{name}") {
+            bail!("defaults.mark_format: must contain {{name}}");
+        }
+        if d.first_workspace < 1 {
+            bail!("defaults.first_workspace: must be at least 1");
+        }
+
+        let actions = ReceivedCmd::command();
+        for (name, action) in self.alias.iter() {
+            if actions.find_subcommand(name).is_some() {
+                bail!("alias.{}: can't replace the built-in action", name);
+            }
+            match split_words(action).first() {
+                Some(first) if actions.find_subcommand(first).is_some() => (),
+                Some(first) => bail!("alias.{}: {} is not an i3-valet action", name, first),
+                None => bail!("alias.{}: is empty", name),
+            }
+        }
+
+        self.macros.prepare()?;
+        self.rules.prepare()?;
+        self.macros.check_actions(&self)?;
+        self.rules.check_actions(&self)?;
+        Ok(self)
+    }
+
+    /// Swap an alias for the action it stands for
+    pub(crate) fn resolve<'a>(&self, action: &'a Action) -> anyhow::Result<Cow<'a, Action>> {
+        let Action::Alias(args) = action else {
+            return Ok(Cow::Borrowed(action));
+        };
+        let (name, rest) = args
+            .split_first()
+            .ok_or_else(|| anyhow!("No action given"))?;
+        let alias = self
+            .alias
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown action or alias: {}", name))?;
+        let words = split_words(alias).into_iter().chain(rest.iter().cloned());
+        let cmd = ReceivedCmd::try_parse_from(words)
+            .with_context(|| format!("Expanding alias {}", name))?;
+        Ok(Cow::Owned(cmd.action))
+    }
+
+    /// Check that an action can run: that its alias is known, and that the actions inside a
+    /// `when` or a `macro` can run too. Those are only parsed once the action runs otherwise, so
+    /// a mistake in them would only show up if and when they are picked.
+    pub(crate) fn check(&self, action: &Action) -> anyhow::Result<()> {
+        self.check_nested(action, 0)
+    }
+
+    fn check_nested(&self, action: &Action, depth: usize) -> anyhow::Result<()> {
+        if depth > MAX_NESTING {
+            bail!("Actions are nested too deeply, does an alias run itself?");
+        }
+        match self.resolve(action)?.as_ref() {
+            Action::When {
+                then, otherwise, ..
+            } => {
+                for (flag, branch) in [("--then", then), ("--else", otherwise)] {
+                    let Some(branch) = branch else {
+                        continue;
+                    };
+                    let key = || format!("{} {}", flag, branch);
+                    let action = parse_action_string(branch).with_context(key)?.action;
+                    self.check_nested(&action, depth + 1).with_context(key)?;
+                }
+            }
+            Action::Macro { name, steps, .. } => {
+                if let Some(name) = name {
+                    if self.macros.get(name).is_none() {
+                        bail!("Unknown macro: {}", name);
+                    }
+                }
+                for step in steps {
+                    let key = || format!("--step {}", step);
+                    if let Cmd::Valet(action) = Cmd::parse(step).with_context(key)? {
+                        self.check_nested(&action, depth + 1).with_context(key)?;
+                    }
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> anyhow::Result<Config> {
+        toml::from_str::<Config>(text)
+            .map_err(anyhow::Error::from)
+            .and_then(Config::validate)
+    }
+
+    fn error(text: &str) -> String {
+        format!("{:#}", parse(text).expect_err("config should be rejected"))
+    }
+
+    #[test]
+    fn empty_config() {
+        parse("").unwrap();
+    }
+
+    #[test]
+    fn aliases_in_rules_and_macros() {
+        parse(
+            r#"
+            [alias]
+            corner = "loc rel se"
+
+            [macro]
+            tidy = ["nop fix", "nop corner"]
+
+            [[rule]]
+            class = "^Pavucontrol$"
+            run = ["floating enable", "nop corner"]
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn unknown_action_in_rule() {
+        let e = error(
+            r#"
+            [[rule]]
+            class = "x"
+            run = ["floating enable", "nop fxi"]
+            "#,
+        );
+        assert!(e.starts_with("rule[0].run[1]: "), "{}", e);
+        assert!(e.contains("fxi"), "{}", e);
+    }
+
+    #[test]
+    fn unknown_action_in_macro() {
+        let e = error(
+            r#"
+            [macro]
+            tidy = ["nop fix", "nop lco rel se"]
+            "#,
+        );
+        assert!(e.starts_with("macro.tidy[1]: "), "{}", e);
+        assert!(e.contains("lco"), "{}", e);
+    }
+
+    #[test]
+    fn alias_for_macro_in_macro() {
+        let e = error(
+            r#"
+            [alias]
+            again = "macro tidy"
+
+            [macro]
+            tidy = ["nop fix", "nop again"]
+            "#,
+        );
+        assert_eq!(e, "macro.tidy[1]: macros can't run other macros");
+    }
+
+    #[test]
+    fn unknown_action_inside_when() {
+        let e = error(
+            r#"
+            [[rule]]
+            class = "x"
+            run = ['nop when floating --then fix --else "lco rel se"']
+            "#,
+        );
+        assert!(
+            e.starts_with("rule[0].run[0]: --else lco rel se: "),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn unknown_macro_inside_when() {
+        let e = error(
+            r#"
+            [[rule]]
+            class = "x"
+            run = ['nop when floating --then "macro tdy"']
+            "#,
+        );
+        assert_eq!(e, "rule[0].run[0]: --then macro tdy: Unknown macro: tdy");
+    }
+
+    #[test]
+    fn alias_that_runs_itself() {
+        let e = error(
+            r#"
+            [alias]
+            again = "when floating --then again"
+
+            [[rule]]
+            class = "x"
+            run = ["nop again"]
+            "#,
+        );
+        assert!(
+            e.ends_with("Actions are nested too deeply, does an alias run itself?"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn alias_must_name_an_action() {
+        let e = error(
+            r#"
+            [alias]
+            corner = "lco rel se"
+            "#,
+        );
+        assert_eq!(e, "alias.corner: lco is not an i3-valet action");
+    }
+}
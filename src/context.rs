@@ -0,0 +1,97 @@
+use std::io;
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_i3ipc::{reply::Node, I3};
+
+use crate::{cache::TreeCache, config::Config, history::History};
+
+/// What to do when i3 rejects one of an action's commands.
+///
+/// An action's commands normally go to i3 in one batch, and i3 runs all of them even when one
+/// fails, so `Report` and `Ignore` only decide how the rejection is reported. `Stop` gives up the
+/// batch to send the commands one at a time instead.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ErrorPolicy {
+    /// fail the action, with every command i3 rejected
+    #[default]
+    Report,
+    /// send the commands one at a time, and fail the action at the first one i3 rejects without
+    /// sending the rest
+    Stop,
+    /// log rejected commands and carry on as if the action succeeded
+    Ignore,
+}
+
+/// What an action has to work with while it runs: the connection to send commands over, the
+/// config, and when run by the listener, the state the listener keeps.
+pub struct Ctx<'a> {
+    pub conn: &'a mut I3,
+    pub config: &'a Config,
+    pub policy: ErrorPolicy,
+    cache: Option<&'a Mutex<TreeCache>>,
+    history: Option<&'a Mutex<History>>,
+}
+
+impl<'a> Ctx<'a> {
+    pub fn new(conn: &'a mut I3, config: &'a Config) -> Self {
+        Ctx {
+            conn,
+            config,
+            policy: ErrorPolicy::default(),
+            cache: None,
+            history: None,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: &'a Mutex<TreeCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_history(mut self, history: &'a Mutex<History>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Get the focus history, which only the listener keeps
+    pub async fn history(&self) -> anyhow::Result<MutexGuard<'a, History>> {
+        match self.history {
+            Some(history) => Ok(history.lock().await),
+            None => Err(anyhow!("Focus history is only kept by i3-valet listen")),
+        }
+    }
+
+    /// Whether the action is being run by the listener, which is all that keeps focus history
+    pub fn in_listener(&self) -> bool {
+        self.history.is_some()
+    }
+
+    /// Get the tree, from the listener's cache if there is one
+    pub async fn tree(&mut self) -> io::Result<Node> {
+        match self.cache {
+            Some(cache) => cache.lock().await.get(self.conn).await,
+            None => self.conn.get_tree().await,
+        }
+    }
+
+    /// Get the tree straight from i3. Floating windows can be moved and resized without i3
+    /// sending an event, so anything that depends on their geometry can't trust the cache.
+    pub async fn fresh_tree(&mut self) -> io::Result<Node> {
+        self.conn.get_tree().await
+    }
+
+    /// Mark the listener's cache stale, since commands are about to change the tree
+    pub async fn invalidate(&mut self) {
+        if let Some(cache) = self.cache {
+            cache.lock().await.invalidate();
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    future::Future,
+    io,
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{context::ErrorPolicy, Action};
+
+/// How long a client gets to send its action once it has connected
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `i3-valet run` waits on the listener, which includes the time the action spends in
+/// the listener's queue
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run an io future, failing with `TimedOut` if it takes longer than `limit`
+async fn timeout<T>(limit: Duration, fut: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(limit, fut).await.unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("timed out after {:?}", limit),
+        ))
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Reply {
+    Done,
+    /// The commands a dry run would have sent
+    Commands(Vec<String>),
+    Failed(String),
+}
+
+/// A job's result holds the commands for a dry run, or `None` for an action that was run
+impl From<anyhow::Result<Option<Vec<String>>>> for Reply {
+    fn from(r: anyhow::Result<Option<Vec<String>>>) -> Self {
+        match r {
+            Ok(None) => Reply::Done,
+            Ok(Some(cmds)) => Reply::Commands(cmds),
+            Err(e) => Reply::Failed(format!("{:#}", e)),
+        }
+    }
+}
+
+/// What a client sends: the action, the error policy to run it with if the client was given
+/// one, and whether to only work out the action's commands
+#[derive(Serialize, Deserialize, Debug)]
+struct Message {
+    action: Action,
+    policy: Option<ErrorPolicy>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+fn runtime_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from)
+}
+
+/// A directory of the user's own in the temp dir, for when there's no `$XDG_RUNTIME_DIR`
+fn fallback_dir() -> PathBuf {
+    let user = std::env::var("USER").unwrap_or_default();
+    std::env::temp_dir().join(format!("i3-valet-{}", user))
+}
+
+/// Where the control socket lives: in `$XDG_RUNTIME_DIR` when it is set, or a directory of the
+/// user's own in the temp dir otherwise.
+pub fn socket_path() -> PathBuf {
+    runtime_dir()
+        .unwrap_or_else(fallback_dir)
+        .join("i3-valet.sock")
+}
+
+/// Make sure only the user can get into `dir`, creating it if it isn't there. Anyone who can
+/// connect to the socket can run actions, including i3's `exec`, and the temp dir is shared, so
+/// a directory someone else could have made or can get into isn't used.
+fn make_private(dir: &Path) -> io::Result<()> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        res => return res,
+    }
+    let meta = fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be a directory only you can use, check who made it",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The socket a running listener takes actions on, so `i3-valet run` can use the listener's
+/// state instead of starting from scratch.
+///
+/// Each client sends one line of JSON holding an [`Action`], an optional [`ErrorPolicy`], and
+/// whether it is a dry run, and gets back one line holding a [`Reply`] once the action has made
+/// it through the listener's queue. The socket file is removed
+/// when this is dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl ControlSocket {
+    /// Bind the control socket. A socket file left behind by a listener that is no longer
+    /// running is replaced, but one with a live listener behind it is an error.
+    pub async fn bind() -> io::Result<Self> {
+        if runtime_dir().is_none() {
+            make_private(&fallback_dir())?;
+        }
+        let path = socket_path();
+        if path.exists() {
+            if UnixStream::connect(&path).await.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another listener is using {}", path.display()),
+                ));
+            }
+            log::debug!("Removing stale control socket {}", path.display());
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+        Ok(ControlSocket { path, listener })
+    }
+
+    /// Wait for the next client to connect. The client's action is read with
+    /// [`Client::request`], which should be done off the accept loop so a slow client doesn't hold
+    /// up the others.
+    pub async fn accept(&self) -> io::Result<Client> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(Client(stream))
+    }
+}
+
+/// A client that has connected, but not yet sent its action
+pub struct Client(UnixStream);
+
+impl Client {
+    /// Read the client's action, giving up if it doesn't arrive in time
+    pub async fn request(self) -> io::Result<Request> {
+        let mut stream = BufReader::new(self.0);
+        let mut line = String::new();
+        timeout(READ_TIMEOUT, stream.read_line(&mut line)).await?;
+        let msg: Message = serde_json::from_str(&line)?;
+        Ok(Request {
+            stream,
+            action: msg.action,
+            policy: msg.policy,
+            dry_run: msg.dry_run,
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// An action sent by a client, which is waiting on the reply
+pub struct Request {
+    stream: BufReader<UnixStream>,
+    pub(crate) action: Action,
+    /// The policy the client asked for, in place of the listener's own
+    pub policy: Option<ErrorPolicy>,
+    /// Reply with the commands the action would send instead of running it
+    pub dry_run: bool,
+}
+
+impl Request {
+    pub async fn reply(mut self, reply: Reply) -> io::Result<()> {
+        let mut line = serde_json::to_string(&reply)?;
+        line.push('\n');
+        self.stream.write_all(line.as_bytes()).await
+    }
+}
+
+/// Hand an action to a running listener and wait for it to be run, or with `dry_run` for the
+/// commands it would send. Returns `None` if there is no listener to send it to.
+pub(crate) async fn send(
+    action: &Action,
+    policy: Option<ErrorPolicy>,
+    dry_run: bool,
+) -> Option<io::Result<Reply>> {
+    let stream = UnixStream::connect(socket_path()).await.ok()?;
+    log::debug!("Forwarding action to listener: {:?}", action);
+    let msg = Message {
+        action: action.clone(),
+        policy,
+        dry_run,
+    };
+    Some(timeout(SEND_TIMEOUT, send_on(stream, &msg)).await)
+}
+
+async fn send_on(stream: UnixStream, msg: &Message) -> io::Result<Reply> {
+    let mut stream = BufReader::new(stream);
+    let mut line = serde_json::to_string(msg)?;
+    line.push('\n');
+    stream.get_mut().write_all(line.as_bytes()).await?;
+
+    line.clear();
+    stream.read_line(&mut line).await?;
+    Ok(serde_json::from_str(&line)?)
+}
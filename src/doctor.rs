@@ -0,0 +1,191 @@
+use anyhow::Context;
+use tokio_i3ipc::I3;
+
+use crate::{config::Config, ext::split_commands, parse_command_string};
+
+/// An i3-valet action in a binding that won't run
+struct Problem {
+    file: String,
+    line: usize,
+    binding: String,
+    error: String,
+}
+
+/// The lines of a config file, with lines continued by a trailing `\` joined together, each with
+/// the number of the line it starts on
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut res = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let (_, joined) = current.get_or_insert_with(|| (i + 1, String::new()));
+        match line.strip_suffix('\\') {
+            Some(part) => joined.push_str(part),
+            None => {
+                joined.push_str(line);
+                res.extend(current.take());
+            }
+        }
+    }
+    res.extend(current);
+    res
+}
+
+/// Split the first word off a string, returning it and the rest
+fn first_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    match end {
+        0 => None,
+        _ => Some((&s[..end], s[end..].trim_start())),
+    }
+}
+
+/// The command a `bindsym` or `bindcode` line runs, or `None` for any other line
+fn binding_command(line: &str) -> Option<&str> {
+    let (first, mut rest) = first_word(line)?;
+    if first != "bindsym" && first != "bindcode" {
+        return None;
+    }
+    // skip the key, and options like --release, which can go before or after it
+    let mut key = false;
+    loop {
+        let (word, after) = first_word(rest)?;
+        if !word.starts_with("--") {
+            if key {
+                break Some(rest);
+            }
+            key = true;
+        }
+        rest = after;
+    }
+}
+
+/// Check the i3-valet actions in one config file. Returns how many were checked.
+fn check_file(file: &str, text: &str, config: &Config, problems: &mut Vec<Problem>) -> usize {
+    let mut checked = 0;
+    for (line, text) in logical_lines(text) {
+        let Some(command) = binding_command(&text) else {
+            continue;
+        };
+        for subcmd in split_commands(command) {
+            let res = match parse_command_string(subcmd) {
+                Ok(Some(cmd)) => config.check(&cmd.action),
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+            checked += 1;
+            if let Err(e) = res {
+                problems.push(Problem {
+                    file: file.to_string(),
+                    line,
+                    binding: text.trim().to_string(),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+    checked
+}
+
+/// Check every i3-valet action bound in the config i3 has loaded, and print the ones that won't
+/// run. Returns false if there were any.
+pub async fn check_config(config: &Config) -> anyhow::Result<bool> {
+    let mut i3 = I3::connect().await.context("Connect to i3")?;
+    let loaded = i3.get_config().await.context("Get i3 config")?;
+
+    let mut problems = Vec::new();
+    // Newer versions of i3 list every file the config was read from, including the main one
+    let checked = match loaded.included_configs {
+        Some(files) if !files.is_empty() => files
+            .iter()
+            .map(|f| {
+                check_file(
+                    &f.path.display().to_string(),
+                    &f.raw_contents,
+                    config,
+                    &mut problems,
+                )
+            })
+            .sum(),
+        _ => check_file("i3 config", &loaded.config, config, &mut problems),
+    };
+
+    for p in problems.iter() {
+        println!("{}:{}: {}", p.file, p.line, p.binding);
+        for line in p.error.lines().filter(|l| !l.trim().is_empty()) {
+            println!("    {}", line);
+        }
+    }
+    println!(
+        "Checked {} i3-valet actions, {} with problems",
+        checked,
+        problems.len()
+    );
+    Ok(problems.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continued_lines_are_joined() {
+        let text = "set $mod Mod4\nbindsym $mod+x \\\n    nop fix\n\nbindsym $mod+y nop loc se\\";
+        assert_eq!(
+            logical_lines(text),
+            [
+                (1, "set $mod Mod4".to_string()),
+                (2, "bindsym $mod+x     nop fix".to_string()),
+                (4, "".to_string()),
+                (5, "bindsym $mod+y nop loc se".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn options_around_the_key_are_skipped() {
+        assert_eq!(binding_command("bindsym $mod+x nop fix"), Some("nop fix"));
+        assert_eq!(
+            binding_command("  bindsym --release $mod+x nop fix"),
+            Some("nop fix")
+        );
+        assert_eq!(
+            binding_command("bindsym $mod+x --release --border nop fix; split h"),
+            Some("nop fix; split h")
+        );
+        assert_eq!(
+            binding_command("bindcode 36 --whole-window kill"),
+            Some("kill")
+        );
+    }
+
+    #[test]
+    fn only_bindings_have_commands() {
+        assert_eq!(binding_command("set $mod Mod4"), None);
+        assert_eq!(binding_command("bindsym $mod+x"), None);
+        assert_eq!(binding_command("bindsymbol $mod+x nop fix"), None);
+        assert_eq!(binding_command(""), None);
+    }
+
+    #[test]
+    fn actions_inside_when_and_macro_are_checked() {
+        let config = Config::default();
+        let text = r#"bindsym $mod+a nop when floating --then "lco se"
+bindsym $mod+b --release nop macro --step "nop fxi"
+bindsym $mod+c nop fix, split h"#;
+        let mut problems = Vec::new();
+        assert_eq!(check_file("i3 config", text, &config, &mut problems), 3);
+        let lines: Vec<usize> = problems.iter().map(|p| p.line).collect();
+        assert_eq!(lines, [1, 2]);
+        assert!(
+            problems[0].error.starts_with("--then lco se: "),
+            "{}",
+            problems[0].error
+        );
+        assert!(
+            problems[1].error.starts_with("--step nop fxi: "),
+            "{}",
+            problems[1].error
+        );
+    }
+}
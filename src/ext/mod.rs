@@ -1,32 +1,83 @@
 mod node_ext;
 mod node_search;
+mod tokenize;
 
 use thiserror::Error;
 use tokio_i3ipc::I3;
 
 pub use node_ext::NodeExt;
 pub use node_search::{Move, NodeSearch, NotFound, Step};
+pub use tokenize::{nop_argument, split_commands, split_criteria_scopes, split_words};
 
 #[derive(Error, Debug)]
-#[error("Command: {cmd} got {err}")]
-pub struct CommandError {
-    cmd: String,
-    err: std::io::Error,
+pub enum CommandError {
+    #[error("Command: {cmd} got {err}")]
+    Io {
+        cmd: String,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Command: {cmd} was rejected by i3 at sub-command {index}: {error}")]
+    Rejected {
+        cmd: String,
+        /// index of the failing sub-command, as split by [`split_commands`]
+        index: usize,
+        /// the error i3 gave
+        error: String,
+    },
 }
 
 impl CommandError {
-    fn new<T: ToString>(cmd: T, err: std::io::Error) -> Self {
-        Self {
+    fn io<T: ToString>(cmd: T, err: std::io::Error) -> Self {
+        CommandError::Io {
             cmd: cmd.to_string(),
             err,
         }
     }
 }
 
-pub async fn i3_command(cmd: &str, conn: &mut I3) -> Result<(), CommandError> {
-    log::debug!("Sending i3 command: {}", cmd);
-    conn.run_command(cmd)
+/// Send several commands to i3 as a single request, so they take effect together and without
+/// the intermediate states showing on screen.
+///
+/// i3 runs every sub-command in the batch even if an earlier one fails, so all the rejected ones
+/// are returned, each mapped back to the command it came from.
+pub async fn i3_batch(cmds: &[String], conn: &mut I3) -> Result<Vec<CommandError>, CommandError> {
+    let batch = cmds.join("; ");
+    log::debug!("Sending i3 commands: {}", batch);
+    let replies = conn
+        .run_command(&batch)
         .await
-        .map(|_| ())
-        .map_err(|e| CommandError::new(cmd, e))
+        .map_err(|e| CommandError::io(&batch, e))?;
+
+    // Which command, and which sub-command within it, each reply is for
+    let origins: Vec<(usize, usize)> = cmds
+        .iter()
+        .enumerate()
+        .flat_map(|(i, cmd)| (0..split_commands(cmd).len()).map(move |sub| (i, sub)))
+        .collect();
+    let mapped = origins.len() == replies.len();
+    if !mapped {
+        log::debug!(
+            "Got {} replies for {} commands, not mapping them back",
+            replies.len(),
+            origins.len()
+        );
+    }
+
+    let mut res = Vec::new();
+    for (i, reply) in replies.into_iter().enumerate() {
+        if reply.success {
+            continue;
+        }
+        let (cmd, index) = match mapped {
+            true => (cmds[origins[i].0].clone(), origins[i].1),
+            false => (batch.clone(), i),
+        };
+        res.push(CommandError::Rejected {
+            cmd,
+            index,
+            error: reply.error.unwrap_or_else(|| "no error given".into()),
+        });
+    }
+    Ok(res)
 }
@@ -1,5 +1,6 @@
 use std::cmp::{Ord, Ordering};
 
+use serde::Serialize;
 use thiserror::Error;
 use tokio_i3ipc::reply::{Node, NodeType};
 
@@ -11,12 +12,18 @@ pub enum NotFound {
     ContentArea,
     #[error("Could not find a focused workspace")]
     Workspace,
+    #[error("Could not find workspace '{0}'")]
+    NamedWorkspace(String),
     #[error("Could not find a focused output")]
     Output,
+    #[error("Could not find output '{0}'")]
+    NamedOutput(String),
     #[error("Could not find a focused window")]
     CurrentWindow,
     #[error("Could not find a focused container with mark '{0}'")]
     Mark(String),
+    #[error("Could not find a previously focused window")]
+    History,
 }
 
 /// An extension trait to i3rpc-rs Node that adds searching functionality
@@ -26,15 +33,27 @@ pub enum NotFound {
 pub trait NodeSearch {
     /// A postorder traversal of the Node and its children as an iterator with
     /// Item = Step
-    fn postorder(&self) -> PostOrder;
+    fn postorder(&self) -> PostOrder<'_>;
 
     /// A preorder traversal of the Node and its children as an iterator with
     /// Item = Step
-    fn preorder(&self) -> PreOrder;
+    fn preorder(&self) -> PreOrder<'_>;
 
     /// Search through the focus path returning the first Node that matches the predicate function.
     fn search_focus_path<P: Fn(&Node) -> bool>(&self, p: P) -> Option<&Node>;
 
+    /// Every container under the Node, tiled or floating, starting with the Node itself. Unlike
+    /// the traversals, this goes into floating containers too.
+    fn all_containers(&self) -> Vec<&Node>;
+
+    /// The workspaces under the Node, in tree order
+    fn workspaces(&self) -> Vec<&Node> {
+        self.all_containers()
+            .into_iter()
+            .filter(|n| n.node_type == NodeType::Workspace)
+            .collect()
+    }
+
     /// Returns the Node of the currently focused workspace
     fn get_current_workspace(&self) -> Result<&Node, NotFound> {
         self.search_focus_path(|n| n.node_type == NodeType::Workspace)
@@ -50,7 +69,7 @@ pub trait NodeSearch {
     /// Returns the "content area" of the currently focused output
     /// (the content area has the rectangle that windows will be in basically: output - bars)
     fn get_content_area(&self) -> Result<&Node, NotFound> {
-        self.search_focus_path(|n| n.name.as_ref().map_or(false, |v| v == "content"))
+        self.search_focus_path(|n| n.name.as_ref().is_some_and(|v| v == "content"))
             .ok_or(NotFound::ContentArea)
     }
 
@@ -88,18 +107,30 @@ impl NodeSearch for Node {
         }
     }
 
-    fn postorder(&self) -> PostOrder {
+    fn all_containers(&self) -> Vec<&Node> {
+        let mut res = Vec::new();
+        for s in self.preorder() {
+            res.push(s.n);
+            for f in s.n.floating_nodes.iter() {
+                res.extend(f.all_containers());
+            }
+        }
+        res
+    }
+
+    fn postorder(&self) -> PostOrder<'_> {
         PostOrder::new(self)
     }
 
-    fn preorder(&self) -> PreOrder {
+    fn preorder(&self) -> PreOrder<'_> {
         PreOrder::new(self)
     }
 }
 
 /// A struct describing how the direction of movement to get to the
 /// current Node in a Step. TODO: describe better
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Move {
     Up,
     Down,
@@ -0,0 +1,301 @@
+/// Split an i3 command string into its sub-commands, the way i3 does: on `;` and `,`, except
+/// inside a quoted argument.
+///
+/// As in i3, a double quote only quotes when it starts an argument (or a criteria value), and a
+/// backslash inside the quotes escapes the next character. `nop` and `exec` take the rest of the
+/// command as one argument, so unless that argument starts with a quote it runs to the next `;` or
+/// `,`, quotes and all.
+///
+/// The pieces are returned untrimmed. Blank pieces are dropped, since i3 doesn't reply to them, so
+/// the count matches the number of replies i3 sends back for the string.
+pub fn split_commands(s: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = start + command_len(&s[start..]);
+        res.push(&s[start..end]);
+        if end == s.len() {
+            break;
+        }
+        start = end + 1;
+    }
+    res.retain(|cmd| !cmd.trim().is_empty());
+    res
+}
+
+/// Split an i3 command string at each `;`, where the criteria stop applying. Each piece holds the
+/// commands that share its criteria, separated by `,`.
+///
+/// Pieces are returned untrimmed, and ones without any commands are dropped.
+pub fn split_criteria_scopes(s: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    loop {
+        end += command_len(&s[end..]);
+        if end == s.len() || s.as_bytes()[end] == b';' {
+            res.push(&s[start..end]);
+            if end == s.len() {
+                break;
+            }
+            start = end + 1;
+        }
+        end += 1;
+    }
+    res.retain(|cmd| !split_commands(cmd).is_empty());
+    res
+}
+
+/// The length of the first command in `s`, up to the `;` or `,` that ends it
+fn command_len(s: &str) -> usize {
+    let b = s.as_bytes();
+    let ends_word = |c: u8| c.is_ascii_whitespace() || c == b';' || c == b',';
+    let mut i = 0;
+    let mut first = true;
+    let mut rest_is_argument = false;
+    loop {
+        while i < b.len() && b[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        match b.get(i) {
+            None | Some(b';' | b',') => return i,
+            Some(b'"') => i = quote_end(s, i),
+            Some(b'[') if first => {
+                // criteria, where a value can be quoted after its `=`
+                while i < b.len() && b[i] != b']' {
+                    i = match (b[i], b.get(i + 1)) {
+                        (b'=', Some(b'"')) => quote_end(s, i + 1),
+                        _ => i + 1,
+                    };
+                }
+                i = (i + 1).min(b.len());
+            }
+            Some(_) if rest_is_argument => {
+                let rest = &s[i..];
+                if let Some(option) = rest.strip_prefix("--no-startup-id") {
+                    if option.bytes().next().is_none_or(ends_word) {
+                        i += "--no-startup-id".len();
+                        continue;
+                    }
+                }
+                return i + rest.find([';', ',']).unwrap_or(rest.len());
+            }
+            Some(_) => {
+                let word = i;
+                while i < b.len() && !ends_word(b[i]) {
+                    i += 1;
+                }
+                rest_is_argument = first && matches!(&s[word..i], "nop" | "exec");
+                first = false;
+            }
+        }
+    }
+}
+
+/// The index just past the quote that closes the one at `open`, or the end of `s` if it isn't
+/// closed
+fn quote_end(s: &str, open: usize) -> usize {
+    let mut escaped = false;
+    for (i, c) in s[open + 1..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return open + 1 + i + 1,
+            _ => (),
+        }
+    }
+    s.len()
+}
+
+/// The argument of a `nop` sub-command, or `None` if it isn't one. i3 hands `nop` a single
+/// argument: either everything after it, or, when that starts with a quote, what's inside the
+/// quotes with `\"` and `\\` unescaped.
+pub fn nop_argument(cmd: &str) -> Option<String> {
+    let rest = cmd.trim_start().strip_prefix("nop")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim();
+    let Some(quoted) = rest.strip_prefix('"') else {
+        return Some(rest.to_string());
+    };
+    let mut arg = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(e @ ('"' | '\\')) => arg.push(e),
+                Some(e) => {
+                    arg.push('\\');
+                    arg.push(e);
+                }
+                None => arg.push('\\'),
+            },
+            '"' => break,
+            c => arg.push(c),
+        }
+    }
+    Some(arg)
+}
+
+/// Split an action into words: on whitespace, except inside double quotes. The quotes are removed,
+/// and inside them `\"` and `\\` stand for `"` and `\`.
+pub fn split_words(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                let w = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some(e @ ('"' | '\\')) => w.push(e),
+                    Some(e) => {
+                        w.push('\\');
+                        w.push(e);
+                    }
+                    None => w.push('\\'),
+                }
+            }
+            '"' => {
+                quoted = !quoted;
+                // a pair of quotes with nothing between them is still a word
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(w) = word.take() {
+                    res.push(w);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(w) = word {
+        res.push(w);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_split_on_semicolons_and_commas() {
+        assert_eq!(
+            split_commands("nop fix; split h, nop loc se"),
+            vec!["nop fix", " split h", " nop loc se"]
+        );
+    }
+
+    #[test]
+    fn commands_keep_quoted_separators() {
+        assert_eq!(
+            split_commands(r#"rename workspace to "a; b, c"; nop fix"#),
+            vec![r#"rename workspace to "a; b, c""#, " nop fix"]
+        );
+        assert_eq!(
+            split_commands(r#"[title="a; b" class=x] kill; nop fix"#),
+            vec![r#"[title="a; b" class=x] kill"#, " nop fix"]
+        );
+    }
+
+    #[test]
+    fn commands_only_quote_from_the_start_of_an_argument() {
+        assert_eq!(
+            split_commands(r#"mark a"b;c"; nop fix"#),
+            vec![r#"mark a"b"#, r#"c""#, " nop fix"]
+        );
+    }
+
+    #[test]
+    fn nop_and_exec_take_the_rest_of_the_command() {
+        assert_eq!(
+            split_commands(r#"nop when workspace "2: web; mail" --then fix"#),
+            vec![r#"nop when workspace "2: web"#, r#" mail" --then fix"#]
+        );
+        assert_eq!(
+            split_commands(r#"exec --no-startup-id "notify-send \"a; b\""; nop fix"#),
+            vec![r#"exec --no-startup-id "notify-send \"a; b\"""#, " nop fix"]
+        );
+        assert_eq!(
+            split_commands(r#"nop "when workspace \"2: web; mail\" --then fix", focus left"#),
+            vec![
+                r#"nop "when workspace \"2: web; mail\" --then fix""#,
+                " focus left"
+            ]
+        );
+    }
+
+    #[test]
+    fn criteria_scopes_split_on_semicolons() {
+        assert_eq!(
+            split_criteria_scopes(
+                r#"[con_id=1] floating enable, move position center; mark "a;b""#
+            ),
+            vec![
+                "[con_id=1] floating enable, move position center",
+                r#" mark "a;b""#
+            ]
+        );
+        assert_eq!(split_criteria_scopes(";nop fix;, ;"), vec!["nop fix"]);
+    }
+
+    #[test]
+    fn nop_argument_is_unquoted() {
+        assert_eq!(nop_argument(" nop loc se ").as_deref(), Some("loc se"));
+        assert_eq!(
+            nop_argument(r#"nop "when workspace \"2: web\" --then fix""#).as_deref(),
+            Some(r#"when workspace "2: web" --then fix"#)
+        );
+        assert_eq!(nop_argument("nop").as_deref(), Some(""));
+        assert_eq!(nop_argument("nope"), None);
+        assert_eq!(nop_argument("split h"), None);
+    }
+
+    #[test]
+    fn commands_keep_escaped_quotes() {
+        assert_eq!(
+            split_commands(r#"exec "echo \"x; y\" \\"; nop fix"#),
+            vec![r#"exec "echo \"x; y\" \\""#, " nop fix"]
+        );
+    }
+
+    #[test]
+    fn commands_drop_blank_pieces() {
+        assert_eq!(split_commands("nop fix;; ,  ;"), vec!["nop fix"]);
+        assert!(split_commands("").is_empty());
+        assert!(split_commands("  ").is_empty());
+    }
+
+    #[test]
+    fn words_split_on_whitespace() {
+        assert_eq!(
+            split_words("  nop\tloc  rel se "),
+            ["nop", "loc", "rel", "se"]
+        );
+    }
+
+    #[test]
+    fn words_unquote() {
+        assert_eq!(
+            split_words(r#"nop when class "^Fire fox$" --then fix"#),
+            ["nop", "when", "class", "^Fire fox$", "--then", "fix"]
+        );
+        assert_eq!(split_words(r#"a"b c"d"#), ["ab cd"]);
+    }
+
+    #[test]
+    fn words_unescape() {
+        assert_eq!(split_words(r#""say \"hi\"""#), [r#"say "hi""#]);
+        assert_eq!(split_words(r#""back\\slash""#), [r"back\slash"]);
+        // other escapes are left as they are
+        assert_eq!(split_words(r#""\d+""#), [r"\d+"]);
+    }
+
+    #[test]
+    fn words_keep_empty_quotes() {
+        assert_eq!(split_words(r#"mark """#), ["mark", ""]);
+    }
+}
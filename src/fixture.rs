@@ -0,0 +1,86 @@
+//! Trees for tests. Containers are built from JSON, as i3 sends them, with everything but the
+//! parts the tests care about filled in. Each container's focus list starts out in the order of
+//! its children, so the first child is on the focus path.
+
+use serde_json::{json, Value};
+use tokio_i3ipc::reply::Node;
+
+fn node(id: usize, node_type: &str, layout: &str, nodes: Vec<Node>) -> Node {
+    let rect = json!({"x": 0, "y": 0, "width": 100, "height": 100});
+    let focus: Vec<usize> = nodes.iter().map(|n| n.id).collect();
+    let value: Value = json!({
+        "id": id,
+        "name": null,
+        "num": null,
+        "type": node_type,
+        "layout": layout,
+        "output": null,
+        "orientation": "none",
+        "border": "normal",
+        "scratchpad_state": "none",
+        "percent": null,
+        "rect": rect,
+        "window_rect": rect,
+        "deco_rect": rect,
+        "geometry": rect,
+        "window": null,
+        "window_properties": null,
+        "window_type": null,
+        "current_border_width": 2,
+        "urgent": false,
+        "marks": [],
+        "focused": false,
+        "focus": focus,
+        "sticky": false,
+        "floating": "auto_off",
+        "floating_nodes": [],
+        "fullscreen_mode": 0,
+        "nodes": nodes,
+    });
+    serde_json::from_value(value).expect("fixture node")
+}
+
+/// A window
+pub fn win(id: usize) -> Node {
+    let mut n = node(id, "con", "splith", vec![]);
+    n.window = Some(id);
+    n.name = Some(format!("win{}", id));
+    n
+}
+
+/// The node, with focus
+pub fn focused(mut n: Node) -> Node {
+    n.focused = true;
+    n
+}
+
+pub fn workspace(id: usize, name: &str, layout: &str, nodes: Vec<Node>) -> Node {
+    let mut n = node(id, "workspace", layout, nodes);
+    n.name = Some(name.to_string());
+    n
+}
+
+/// A split container
+pub fn con(id: usize, layout: &str, nodes: Vec<Node>) -> Node {
+    node(id, "con", layout, nodes)
+}
+
+/// An output, with its workspaces in a content area with the next id
+pub fn output(id: usize, name: &str, workspaces: Vec<Node>) -> Node {
+    let mut content = node(id + 1, "con", "splith", workspaces);
+    content.name = Some("content".to_string());
+    let mut n = node(id, "output", "output", vec![content]);
+    n.name = Some(name.to_string());
+    n
+}
+
+/// The root of the tree, with id 1
+pub fn root(outputs: Vec<Node>) -> Node {
+    node(1, "root", "splith", outputs)
+}
+
+/// The node, taking up `percent` of its parent
+pub fn percent(mut n: Node, percent: f64) -> Node {
+    n.percent = Some(percent);
+    n
+}
@@ -1,9 +1,10 @@
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use tokio_i3ipc::reply::{Node, Rect};
 
 use crate::ext::{NodeSearch, NotFound};
 
-#[derive(ValueEnum, Debug, Clone, Copy)]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Pos {
     /// top-left corner
     NW,
@@ -22,9 +23,11 @@ pub enum Pos {
     Left,
     /// center-right edge
     Right,
+    /// middle of the screen
+    Center,
 }
 
-#[derive(ValueEnum, Debug, Clone, Copy)]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Positioning {
     /// relative to the output (will overlap bars)
     #[value(name = "abs")]
@@ -78,6 +81,7 @@ impl DisplayArea {
             Pos::Bottom => ((x + w / 2 - ww / 2), (y + h - wh)),
             Pos::Right => ((x + w - ww), (y + h / 2 - wh / 2)),
             Pos::Left => (x, (y + h / 2 - wh / 2)),
+            Pos::Center => ((x + w / 2 - ww / 2), (y + h / 2 - wh / 2)),
         }
     }
 }
@@ -0,0 +1,214 @@
+use clap::ValueEnum;
+
+use crate::{
+    floats::Pos,
+    manage::LayoutAction,
+    output::{Change, Direction},
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    /// a mode for moving floating windows to anchor points
+    Float,
+    /// a mode for focusing and moving things between outputs
+    Output,
+    /// keys for the layout main actions
+    Layout,
+    /// all of the above
+    All,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keys {
+    /// hjkl, with yubn for the corners
+    Vim,
+    /// the arrow keys, with home, end, page up and page down for the corners
+    Arrows,
+    /// the numeric keypad
+    Numpad,
+}
+
+impl Keys {
+    fn pos(self, pos: Pos) -> &'static str {
+        use Keys::*;
+        match (self, pos) {
+            (Vim, Pos::NW) => "y",
+            (Vim, Pos::NE) => "u",
+            (Vim, Pos::SW) => "b",
+            (Vim, Pos::SE) => "n",
+            (Vim, Pos::Top) => "k",
+            (Vim, Pos::Bottom) => "j",
+            (Vim, Pos::Left) => "h",
+            (Vim, Pos::Right) => "l",
+            (Vim, Pos::Center) => "c",
+            (Arrows, Pos::NW) => "Home",
+            (Arrows, Pos::NE) => "Prior",
+            (Arrows, Pos::SW) => "End",
+            (Arrows, Pos::SE) => "Next",
+            (Arrows, Pos::Top) => "Up",
+            (Arrows, Pos::Bottom) => "Down",
+            (Arrows, Pos::Left) => "Left",
+            (Arrows, Pos::Right) => "Right",
+            (Arrows, Pos::Center) => "space",
+            (Numpad, Pos::NW) => "KP_7",
+            (Numpad, Pos::NE) => "KP_9",
+            (Numpad, Pos::SW) => "KP_1",
+            (Numpad, Pos::SE) => "KP_3",
+            (Numpad, Pos::Top) => "KP_8",
+            (Numpad, Pos::Bottom) => "KP_2",
+            (Numpad, Pos::Left) => "KP_4",
+            (Numpad, Pos::Right) => "KP_6",
+            (Numpad, Pos::Center) => "KP_5",
+        }
+    }
+
+    fn dir(self, dir: Direction) -> &'static str {
+        use Keys::*;
+        match (self, dir) {
+            (Vim, Direction::Next) => "l",
+            (Vim, Direction::Prev) => "h",
+            (Arrows, Direction::Next) => "Right",
+            (Arrows, Direction::Prev) => "Left",
+            (Numpad, Direction::Next) => "KP_6",
+            (Numpad, Direction::Prev) => "KP_4",
+        }
+    }
+}
+
+/// The modifiers added to a direction key for each kind of output change
+fn change_modifiers(change: Change) -> &'static str {
+    match change {
+        Change::Focus => "",
+        Change::MoveWin => "Shift+",
+        Change::MoveWs => "Control+",
+    }
+}
+
+fn layout_key(action: LayoutAction) -> &'static str {
+    match action {
+        LayoutAction::Set => "Shift+i",
+        LayoutAction::Focus => "i",
+        LayoutAction::Swap => "Control+i",
+        LayoutAction::SwapRecent => "Control+Shift+i",
+    }
+}
+
+/// How a value is written on the command line
+fn name<T: ValueEnum>(v: T) -> String {
+    v.to_possible_value()
+        .map(|p| p.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// The end of a mode block, with keys to leave it
+fn end_mode(lines: &mut Vec<String>) {
+    lines.push("    bindsym Return mode \"default\"".into());
+    lines.push("    bindsym Escape mode \"default\"".into());
+    lines.push("}".into());
+}
+
+fn float_mode(keys: Keys, modifier: &str) -> Vec<String> {
+    let mut lines = vec![
+        "# i3-valet: move floating windows to anchor points".to_string(),
+        "mode \"valet-float\" {".to_string(),
+    ];
+    for pos in Pos::value_variants() {
+        lines.push(format!(
+            "    bindsym {} nop loc {}",
+            keys.pos(*pos),
+            name(*pos)
+        ));
+    }
+    end_mode(&mut lines);
+    lines.push(format!("bindsym {}+m mode \"valet-float\"", modifier));
+    lines
+}
+
+fn output_mode(keys: Keys, modifier: &str) -> Vec<String> {
+    let mut lines = vec![
+        "# i3-valet: focus and move things between outputs".to_string(),
+        "mode \"valet-output\" {".to_string(),
+    ];
+    for change in Change::value_variants() {
+        for dir in Direction::value_variants() {
+            lines.push(format!(
+                "    bindsym {}{} nop output {} {}",
+                change_modifiers(*change),
+                keys.dir(*dir),
+                name(*change),
+                name(*dir)
+            ));
+        }
+    }
+    end_mode(&mut lines);
+    lines.push(format!("bindsym {}+o mode \"valet-output\"", modifier));
+    lines
+}
+
+fn layout_keys(modifier: &str) -> Vec<String> {
+    let mut lines = vec!["# i3-valet: layout main window".to_string()];
+    for action in LayoutAction::value_variants() {
+        lines.push(format!(
+            "bindsym {}+{} nop layout main {}",
+            modifier,
+            layout_key(*action),
+            name(*action)
+        ));
+    }
+    lines
+}
+
+/// i3 config with bindings for a family of actions, ready to be included in an i3 config
+pub fn generate(family: Family, keys: Keys, modifier: &str) -> String {
+    let mut sections = Vec::new();
+    if matches!(family, Family::Float | Family::All) {
+        sections.push(float_mode(keys, modifier));
+    }
+    if matches!(family, Family::Output | Family::All) {
+        sections.push(output_mode(keys, modifier));
+    }
+    if matches!(family, Family::Layout | Family::All) {
+        sections.push(layout_keys(modifier));
+    }
+    let sections: Vec<String> = sections.iter().map(|lines| lines.join("\n")).collect();
+    sections.join("\n\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, ext::split_commands, parse_command_string};
+
+    #[test]
+    fn every_generated_action_parses() {
+        let valet = Config::default();
+        for family in Family::value_variants() {
+            for keys in Keys::value_variants() {
+                let config = generate(*family, *keys, "$mod");
+                let mut actions = 0;
+                for line in config.lines().map(str::trim) {
+                    let Some(binding) = line.strip_prefix("bindsym ") else {
+                        continue;
+                    };
+                    let (_, command) = binding.split_once(' ').unwrap();
+                    for subcmd in split_commands(command) {
+                        // an unknown action parses as an alias, which only check turns away
+                        let res = match parse_command_string(subcmd) {
+                            Ok(Some(cmd)) => valet.check(&cmd.action),
+                            Ok(None) => {
+                                assert!(!subcmd.starts_with("nop"), "{}", line);
+                                continue;
+                            }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = res {
+                            panic!("{:?} {:?}: {}: {:#}", family, keys, line, e);
+                        }
+                        actions += 1;
+                    }
+                }
+                assert!(actions > 0, "{:?} {:?}", family, keys);
+            }
+        }
+    }
+}
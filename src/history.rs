@@ -0,0 +1,287 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use tokio_i3ipc::reply::Node;
+
+use crate::{
+    ext::{NodeSearch, NotFound},
+    output::Direction,
+};
+
+/// How many windows to remember
+const MAX_HISTORY: usize = 64;
+
+/// How long a cycle stays open without being stepped. Cycles are meant to be committed by
+/// leaving a binding mode, but the mode can change before the step that starts the cycle runs,
+/// and a cycle started outside a mode has nothing to commit it. Those end up committed once this
+/// passes.
+const CYCLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The windows that have had focus, most recent first. The listener records every window focus
+/// event here.
+///
+/// Only one list is kept; the history for a workspace is this list narrowed down to the windows
+/// that are on that workspace now, so it stays right when windows move between workspaces.
+///
+/// Windows are kept by their X11 window id rather than their container id, since i3 gives every
+/// container a new id when it restarts, and the history is kept across restarts.
+#[derive(Default)]
+pub struct History {
+    windows: VecDeque<usize>,
+    cycle: Option<Cycle>,
+}
+
+/// An alt-tab style walk through the history. Focus changes made while cycling don't reorder
+/// the history until the cycle is committed.
+struct Cycle {
+    windows: Vec<usize>,
+    pos: usize,
+    /// The windows the cycle has focused. Focus events for any other window mean focus was moved
+    /// some other way, which ends the cycle.
+    visited: HashSet<usize>,
+    last_step: Instant,
+}
+
+impl Cycle {
+    fn expired(&self) -> bool {
+        self.last_step.elapsed() > CYCLE_TIMEOUT
+    }
+}
+
+impl History {
+    /// Record that a window, by X11 id, got focus. Focus events from stepping the current cycle are left out,
+    /// and any other focus change commits the cycle first.
+    pub fn focused(&mut self, id: usize) {
+        if let Some(cycle) = &self.cycle {
+            if cycle.visited.contains(&id) && !cycle.expired() {
+                return;
+            }
+            self.commit();
+        }
+        self.record(id);
+    }
+
+    fn record(&mut self, id: usize) {
+        self.windows.retain(|w| *w != id);
+        self.windows.push_front(id);
+        self.windows.truncate(MAX_HISTORY);
+    }
+
+    /// Forget a window that has closed
+    pub fn closed(&mut self, id: usize) {
+        self.windows.retain(|w| *w != id);
+    }
+
+    /// The most recently focused windows that are still in `within`, most recent first
+    pub fn recent<'a>(&self, within: &'a Node) -> Vec<&'a Node> {
+        let present: HashMap<usize, &Node> = within
+            .all_containers()
+            .into_iter()
+            .filter_map(|n| n.window.map(|w| (w, n)))
+            .collect();
+        self.windows
+            .iter()
+            .filter_map(|w| present.get(w).copied())
+            .collect()
+    }
+
+    /// Step the current cycle, starting one if there isn't one going. Windows are given and
+    /// returned by X11 id. Returns the window to focus.
+    pub fn cycle(&mut self, within: &Node, current: usize, dir: Direction) -> Option<usize> {
+        if self.cycle.as_ref().is_some_and(Cycle::expired) {
+            self.commit();
+        }
+        let recent: Vec<usize> = self
+            .recent(within)
+            .into_iter()
+            .filter_map(|n| n.window)
+            .collect();
+        let cycle = self.cycle.get_or_insert_with(|| {
+            let mut windows = recent;
+            windows.retain(|w| *w != current);
+            windows.insert(0, current);
+            Cycle {
+                windows,
+                pos: 0,
+                visited: HashSet::from([current]),
+                last_step: Instant::now(),
+            }
+        });
+
+        let len = cycle.windows.len();
+        if len < 2 {
+            self.cycle = None;
+            return None;
+        }
+        cycle.pos = match dir {
+            Direction::Next => (cycle.pos + 1) % len,
+            Direction::Prev => (cycle.pos + len - 1) % len,
+        };
+        cycle.visited.insert(cycle.windows[cycle.pos]);
+        cycle.last_step = Instant::now();
+        Some(cycle.windows[cycle.pos])
+    }
+
+    /// Finish the current cycle, recording the window it ended on as the most recent
+    pub fn commit(&mut self) {
+        if let Some(cycle) = self.cycle.take() {
+            self.record(cycle.windows[cycle.pos]);
+        }
+    }
+}
+
+fn scope(tree: &Node, workspace: bool) -> Result<&Node, NotFound> {
+    match workspace {
+        true => tree.get_current_workspace(),
+        false => Ok(tree),
+    }
+}
+
+/// Focus a window by X11 id
+fn focus(window: usize) -> String {
+    format!("[id={}] focus", window)
+}
+
+/// Focus the window that was focused `n` focus changes ago
+pub fn focus_back(
+    tree: &Node,
+    history: &History,
+    n: usize,
+    workspace: bool,
+) -> Result<Vec<String>, NotFound> {
+    let current = tree.get_current_window()?;
+    let window = history
+        .recent(scope(tree, workspace)?)
+        .into_iter()
+        .filter_map(|w| w.window)
+        .filter(|w| Some(*w) != current.window)
+        .nth(n.saturating_sub(1))
+        .ok_or(NotFound::History)?;
+    Ok(vec![focus(window)])
+}
+
+/// Focus the next or previous window in the current cycle through the history
+pub fn focus_cycle(
+    tree: &Node,
+    history: &mut History,
+    dir: Direction,
+    workspace: bool,
+) -> Result<Vec<String>, NotFound> {
+    let current = tree
+        .get_current_window()?
+        .window
+        .ok_or(NotFound::CurrentWindow)?;
+    let window = history
+        .cycle(scope(tree, workspace)?, current, dir)
+        .ok_or(NotFound::History)?;
+    Ok(vec![focus(window)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{focused, win, workspace};
+
+    /// A history of focusing windows 1 to 4 in order, on a workspace holding all of them
+    fn setup() -> (History, Node) {
+        let ws = workspace(10, "1", "splith", (1..=4).map(win).collect());
+        let mut history = History::default();
+        for id in 1..=4 {
+            history.focused(id);
+        }
+        (history, ws)
+    }
+
+    /// The X11 ids of the recent windows on `ws`
+    fn recent(history: &History, ws: &Node) -> Vec<usize> {
+        history.recent(ws).iter().filter_map(|n| n.window).collect()
+    }
+
+    #[test]
+    fn most_recent_first() {
+        let (mut history, ws) = setup();
+        assert_eq!(recent(&history, &ws), [4, 3, 2, 1]);
+        history.focused(2);
+        assert_eq!(recent(&history, &ws), [2, 4, 3, 1]);
+        history.closed(4);
+        assert_eq!(recent(&history, &ws), [2, 3, 1]);
+    }
+
+    #[test]
+    fn cycle_commits_where_it_ends() {
+        let (mut history, ws) = setup();
+        assert_eq!(history.cycle(&ws, 4, Direction::Next), Some(3));
+        history.focused(3);
+        assert_eq!(history.cycle(&ws, 3, Direction::Next), Some(2));
+        history.focused(2);
+        // the cycle's own focus changes don't reorder the history
+        assert_eq!(recent(&history, &ws), [4, 3, 2, 1]);
+        history.commit();
+        assert_eq!(recent(&history, &ws), [2, 4, 3, 1]);
+    }
+
+    #[test]
+    fn late_focus_events_from_the_cycle_are_ignored() {
+        let (mut history, ws) = setup();
+        history.cycle(&ws, 4, Direction::Next);
+        history.cycle(&ws, 4, Direction::Next);
+        // the event for the first step comes in after the second step
+        history.focused(3);
+        history.focused(2);
+        assert_eq!(recent(&history, &ws), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn other_focus_ends_cycle() {
+        let (mut history, ws) = setup();
+        assert_eq!(history.cycle(&ws, 4, Direction::Next), Some(3));
+        history.focused(3);
+        // focus moved without the cycle, e.g. the mode was left before the step ran
+        history.focused(1);
+        assert_eq!(recent(&history, &ws), [1, 3, 4, 2]);
+        // and the next step starts a new cycle from the focused window
+        assert_eq!(history.cycle(&ws, 1, Direction::Next), Some(3));
+    }
+
+    #[test]
+    fn stale_cycle_expires() {
+        let (mut history, ws) = setup();
+        assert_eq!(history.cycle(&ws, 4, Direction::Next), Some(3));
+        history.focused(3);
+        let stale = Instant::now() - CYCLE_TIMEOUT * 2;
+        history.cycle.as_mut().unwrap().last_step = stale;
+
+        // a new cycle starts from where the old one ended
+        assert_eq!(history.cycle(&ws, 3, Direction::Next), Some(4));
+        assert_eq!(recent(&history, &ws), [3, 4, 2, 1]);
+
+        // and focus events are recorded again once a cycle has expired
+        history.cycle.as_mut().unwrap().last_step = stale;
+        history.focused(4);
+        assert_eq!(recent(&history, &ws), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn history_survives_new_container_ids() {
+        let (history, _) = setup();
+        // after a restart, i3 has the same windows in new containers
+        let moved = |id: usize| {
+            let mut n = win(id + 100);
+            n.window = Some(id);
+            n
+        };
+        let windows = (1..=4).rev().map(|id| match id {
+            4 => focused(moved(id)),
+            _ => moved(id),
+        });
+        let ws = workspace(20, "1", "splith", windows.collect());
+        let cons: Vec<usize> = history.recent(&ws).iter().map(|n| n.id).collect();
+        assert_eq!(cons, [104, 103, 102, 101]);
+        assert_eq!(
+            focus_back(&ws, &history, 1, false).unwrap(),
+            ["[id=3] focus"]
+        );
+    }
+}
@@ -1,13 +1,23 @@
-use std::fmt::Write;
+use std::collections::HashSet;
 
-use clap::ValueEnum;
-use tokio_i3ipc::reply::Node;
+use anyhow::{bail, Context};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio_i3ipc::{
+    event::Subscribe,
+    reply::{Node, NodeLayout, Rect},
+    I3,
+};
+use tokio_stream::StreamExt;
 
-use crate::ext::{NodeExt, NodeSearch, NotFound, Step};
+use crate::{
+    collapse,
+    context::Ctx,
+    ext::{NodeExt, NodeSearch, Step},
+};
 
-use lazy_static::lazy_static;
-
-#[derive(ValueEnum, Clone, Debug, Copy)]
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PrintTarget {
     /// print the whole tree
     Tree,
@@ -15,163 +25,597 @@ pub enum PrintTarget {
     Rects,
     /// print info about only the current window
     Window,
+    /// print each workspace with its output, whether it's visible or urgent, and how many windows
+    /// it has
+    Workspaces,
+    /// print each output with whether it's active or primary, its rect and current workspace
+    Outputs,
+    /// print each mark with the window that has it and that window's workspace
+    Marks,
+    /// print the whole tree, across every output
+    All,
+    /// print what `fix` would collapse on the current workspace and why, along with other
+    /// nesting that makes layouts harder to work with
+    Lint,
 }
 
-pub fn run(target: PrintTarget, tree: &Node) -> Result<(), NotFound> {
-    //let node = conn.get_tree().expect("get_tree 1");
-    let (to_print, fmt) = match target {
-        PrintTarget::Tree => (tree.get_current_workspace()?, &*STD),
-        PrintTarget::Rects => (tree.get_current_output()?, &*RECT),
-        PrintTarget::Window => (tree.get_current_window()?, &*WINDOW),
-    };
+impl PrintTarget {
+    /// Whether the target prints containers from the tree, which have fields to pick from
+    fn containers(self) -> bool {
+        matches!(
+            self,
+            PrintTarget::Tree | PrintTarget::Rects | PrintTarget::Window | PrintTarget::All
+        )
+    }
+}
 
-    pretty_print(to_print, fmt);
-    Ok(())
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Format {
+    /// columns for reading
+    #[default]
+    Text,
+    /// a JSON array with an object for each container
+    Json,
+    /// a JSON object for each container, one per line
+    Jsonl,
+    /// tab separated columns with a header row
+    Tsv,
+    /// a Graphviz graph, coloured by layout with the focus in red
+    Dot,
+    /// the tree drawn with box-drawing lines
+    Ascii,
 }
 
-lazy_static! {
-    static ref STD: StepFormatter = {
-        let mut fmt = StepFormatter::new();
-        fmt.id = true;
-        fmt.depth = true;
-        fmt.name = true;
-        fmt.layout = true;
-        fmt.marks = true;
-        fmt.moveto = true;
-        fmt
-    };
-    static ref RECT: StepFormatter = {
-        let mut fmt = StepFormatter::new();
-        fmt.id = true;
-        fmt.depth = true;
-        fmt.name = true;
-        fmt.rect = true;
-        fmt
-    };
-    static ref WINDOW: StepFormatter = {
-        let mut fmt = StepFormatter::new();
-        fmt.show_indent(false);
-        fmt.id = true;
-        fmt.floating = true;
-        fmt.depth = true;
-        fmt.name = true;
-        fmt.layout = true;
-        fmt.marks = true;
-        fmt
-    };
+impl Format {
+    /// Whether the format is for people rather than scripts
+    fn readable(self) -> bool {
+        matches!(self, Format::Text | Format::Dot | Format::Ascii)
+    }
+
+    /// Whether the format shows the shape of the tree itself
+    fn drawn(self) -> bool {
+        matches!(self, Format::Dot | Format::Ascii)
+    }
 }
 
-struct StepFormatter {
-    // shorten id since much of it is redundant
-    short_id: bool,
-    // if depth is on, indent by depth spaces
-    indent: bool,
-    // track columns
-    depth: bool,
-    id: bool,
-    name: bool,
-    focus: bool,
-    layout: bool,
-    rect: bool,
-    marks: bool,
-    floating: bool,
-    moveto: bool,
-}
-
-impl StepFormatter {
-    fn new() -> Self {
-        StepFormatter {
-            indent: true,
-            short_id: true,
-            depth: false,
-            id: false,
-            name: false,
-            focus: false,
-            layout: false,
-            rect: false,
-            marks: false,
-            floating: false,
-            moveto: false,
+/// Something to print about each container
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Field {
+    /// how deep in the tree the container is
+    Depth,
+    /// the way the traversal moved to get to the container: down, up or sibling
+    Move,
+    Id,
+    Name,
+    /// the window class
+    Class,
+    /// whether the container is focused
+    Focus,
+    Layout,
+    /// size and position, as WIDTHxHEIGHT+X+Y
+    Rect,
+    Marks,
+    Floating,
+}
+
+/// The fields each target prints as text when none are asked for
+const STD: &[Field] = &[
+    Field::Depth,
+    Field::Name,
+    Field::Id,
+    Field::Layout,
+    Field::Marks,
+    Field::Move,
+];
+const RECT: &[Field] = &[Field::Depth, Field::Name, Field::Id, Field::Rect];
+const WINDOW: &[Field] = &[
+    Field::Id,
+    Field::Floating,
+    Field::Depth,
+    Field::Name,
+    Field::Layout,
+    Field::Marks,
+];
+
+/// The fields the structured formats give when none are asked for
+const ALL: &[Field] = &[
+    Field::Depth,
+    Field::Move,
+    Field::Id,
+    Field::Name,
+    Field::Class,
+    Field::Focus,
+    Field::Layout,
+    Field::Rect,
+    Field::Marks,
+    Field::Floating,
+];
+
+/// How to print the containers
+#[derive(Args, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrintOptions {
+    /// how to print it
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+    /// the fields to print, separated by commas. Each target has its own set by default.
+    #[arg(long, value_delimiter = ',')]
+    pub fields: Vec<Field>,
+    /// print whole ids in text, rather than just the last 5 digits
+    #[arg(long)]
+    pub full_id: bool,
+    /// don't indent names in text by their depth in the tree
+    #[arg(long)]
+    pub no_indent: bool,
+    /// keep printing, redrawing every time a window, workspace or binding event comes in
+    #[arg(long)]
+    pub watch: bool,
+}
+
+impl Field {
+    fn name(self) -> String {
+        self.to_possible_value()
+            .map(|p| p.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    fn json(self, s: &Step) -> Value {
+        let n = s.n;
+        match self {
+            Field::Depth => json!(s.d),
+            Field::Move => json!(s.m),
+            Field::Id => json!(n.id),
+            Field::Name => json!(n.name),
+            Field::Class => json!(class(n)),
+            Field::Focus => json!(n.focused),
+            Field::Layout => json!(n.layout),
+            Field::Rect => json!(n.rect),
+            Field::Marks => json!(n.marks.as_ref().map_or(&[][..], |m| &m.0)),
+            Field::Floating => json!(n.is_floating()),
+        }
+    }
+
+    fn text(self, s: &Step, opts: &PrintOptions) -> String {
+        let n = s.n;
+        match self {
+            Field::Id => id(n.id, opts),
+            Field::Name if !opts.no_indent && opts.format == Format::Text => {
+                format!(
+                    "{}{}",
+                    " ".repeat(s.d),
+                    n.name.as_deref().unwrap_or_default()
+                )
+            }
+            _ => cell(&self.json(s)),
         }
     }
+}
 
-    fn show_indent(&mut self, v: bool) -> &mut StepFormatter {
-        self.indent = v;
-        self
+/// An id as text. Ids are long, and the last 5 digits are enough to tell them apart when reading.
+fn id(id: usize, opts: &PrintOptions) -> String {
+    let id = id.to_string();
+    match !opts.full_id && opts.format.readable() {
+        true => id[id.len().saturating_sub(5)..].to_string(),
+        false => id,
     }
+}
 
-    #[allow(dead_code)]
-    fn short_id(&mut self, v: bool) -> &mut StepFormatter {
-        self.short_id = v;
-        self
+/// A value as text for a column
+fn cell(v: &Value) -> String {
+    match v {
+        Value::Null => String::new(),
+        // tabs and newlines in names would break up the columns
+        Value::String(v) => v.replace(['\t', '\n'], " "),
+        Value::Array(vs) => vs.iter().map(cell).collect::<Vec<_>>().join(","),
+        Value::Object(_) => match serde_json::from_value::<Rect>(v.clone()) {
+            Ok(r) => format!("{}x{}+{}+{}", r.width, r.height, r.x, r.y),
+            Err(_) => v.to_string(),
+        },
+        v => v.to_string(),
     }
+}
 
-    fn format(&self, s: &Step) -> String {
-        let mut out = String::with_capacity(128);
-        if self.depth {
-            for _ in 0..s.d {
-                out.push(' ');
-            }
-            write!(out, "{}", s.d).unwrap();
+fn class(n: &Node) -> Option<&str> {
+    n.window_properties
+        .as_ref()
+        .and_then(|p| p.class.as_deref())
+}
+
+/// Rows to print in one of the formats that don't draw the tree, as values for the structured
+/// formats and as text for the others
+struct Table {
+    columns: Vec<String>,
+    values: Vec<Vec<Value>>,
+    cells: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(columns: &[&str], values: Vec<Vec<Value>>, opts: &PrintOptions) -> Self {
+        let cells = values
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(columns)
+                    .map(|(v, c)| match (*c, v) {
+                        ("id" | "parent", Value::Number(n)) => {
+                            id(n.as_u64().unwrap_or_default() as usize, opts)
+                        }
+                        _ => cell(v),
+                    })
+                    .collect()
+            })
+            .collect();
+        Table {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            values,
+            cells,
         }
+    }
 
-        if self.name {
-            out.push_str(s.n.name.as_deref().unwrap_or("None"));
+    fn containers(n: &Node, fields: &[Field], opts: &PrintOptions) -> Self {
+        let steps: Vec<Step> = n.preorder().collect();
+        Table {
+            columns: fields.iter().map(|f| f.name()).collect(),
+            values: steps
+                .iter()
+                .map(|s| fields.iter().map(|f| f.json(s)).collect())
+                .collect(),
+            cells: steps
+                .iter()
+                .map(|s| fields.iter().map(|f| f.text(s, opts)).collect())
+                .collect(),
         }
+    }
+
+    fn records(&self) -> impl Iterator<Item = Value> + '_ {
+        self.values.iter().map(|row| {
+            let map: Map<String, Value> = self.columns.iter().cloned().zip(row.clone()).collect();
+            Value::Object(map)
+        })
+    }
 
-        if self.id {
-            if self.short_id {
-                let s = format!("{}", s.n.id);
-                let last = &s[s.len() - 5..];
-                out.push_str(last);
-            } else {
-                write!(out, "{}", s.n.id).unwrap();
+    fn render(&self, format: Format) -> Vec<String> {
+        match format {
+            Format::Json => {
+                let records: Vec<_> = self.records().collect();
+                let json = serde_json::to_string_pretty(&records).unwrap();
+                json.lines().map(String::from).collect()
             }
+            Format::Jsonl => self.records().map(|r| r.to_string()).collect(),
+            Format::Tsv => std::iter::once(&self.columns)
+                .chain(self.cells.iter())
+                .map(|row| row.join("\t"))
+                .collect(),
+            _ => self.pretty_print(),
         }
-        if self.focus {
-            match s.n.focused {
-                true => out.push('F'),
-                false => out.push('U'),
-            };
-        }
-        if self.layout {
-            write!(out, "{:?}", s.n.layout).unwrap();
-        }
-        if self.rect {
-            write!(out, "{:?}", s.n.rect).unwrap();
+    }
+
+    /// The cells in columns lined up under a header
+    fn pretty_print(&self) -> Vec<String> {
+        let header: Vec<_> = self.columns.iter().map(|c| c.to_uppercase()).collect();
+        let rows: Vec<&Vec<String>> = std::iter::once(&header).chain(self.cells.iter()).collect();
+
+        let mut widths = vec![0; self.columns.len()];
+        for row in rows.iter() {
+            for (w, cell) in widths.iter_mut().zip(row.iter()) {
+                *w = (*w).max(cell.chars().count());
+            }
         }
-        if self.marks {
-            if let Some(ref marks) = s.n.marks {
-                out.push_str(marks.0.join(",").as_ref());
+        rows.iter()
+            .map(|row| {
+                let line: Vec<_> = row
+                    .iter()
+                    .zip(widths.iter())
+                    .map(|(cell, w)| format!("{:w$}", cell, w = w))
+                    .collect();
+                line.join("  ").trim_end().to_string()
+            })
+            .collect()
+    }
+}
+
+async fn workspaces(ctx: &mut Ctx<'_>, tree: &Node, opts: &PrintOptions) -> anyhow::Result<Table> {
+    let workspaces = ctx
+        .conn
+        .get_workspaces()
+        .await
+        .context("Get workspaces for Print")?;
+    let rows = workspaces
+        .iter()
+        .map(|ws| {
+            // i3 gives workspaces the same id as their container in the tree
+            let windows = tree
+                .workspaces()
+                .into_iter()
+                .find(|n| n.id == ws.id)
+                .map_or(0, |n| {
+                    n.all_containers()
+                        .iter()
+                        .filter(|c| c.window.is_some())
+                        .count()
+                });
+            vec![
+                json!(ws.num),
+                json!(ws.name),
+                json!(ws.output),
+                json!(ws.visible),
+                json!(ws.urgent),
+                json!(windows),
+            ]
+        })
+        .collect();
+    let columns = ["num", "name", "output", "visible", "urgent", "windows"];
+    Ok(Table::new(&columns, rows, opts))
+}
+
+async fn outputs(ctx: &mut Ctx<'_>, opts: &PrintOptions) -> anyhow::Result<Table> {
+    let outputs = ctx
+        .conn
+        .get_outputs()
+        .await
+        .context("Get outputs for Print")?;
+    let rows = outputs
+        .iter()
+        .map(|o| {
+            vec![
+                json!(o.name),
+                json!(o.active),
+                json!(o.primary),
+                json!(o.rect),
+                json!(o.current_workspace),
+            ]
+        })
+        .collect();
+    let columns = ["name", "active", "primary", "rect", "workspace"];
+    Ok(Table::new(&columns, rows, opts))
+}
+
+fn marks(tree: &Node, opts: &PrintOptions) -> Table {
+    let mut rows = Vec::new();
+    for ws in tree.workspaces() {
+        for n in ws.all_containers() {
+            for mark in n.marks.iter().flat_map(|m| m.0.iter()) {
+                rows.push(vec![
+                    json!(mark),
+                    json!(n.id),
+                    json!(n.name),
+                    json!(ws.name),
+                ]);
             }
         }
-        if self.floating {
-            write!(out, "{:.1}", s.n.is_floating()).unwrap();
+    }
+    Table::new(&["mark", "id", "name", "workspace"], rows, opts)
+}
+
+/// Explain what `fix` would do to a workspace, and point out nesting it leaves alone
+fn lint(ws: &Node, opts: &PrintOptions) -> Table {
+    let ids = |chain: &[&Node]| {
+        let ids: Vec<_> = chain.iter().map(|n| id(n.id, opts)).collect();
+        ids.join(", ")
+    };
+    let plan = collapse::plan(ws, &[]);
+    let mut rows = Vec::new();
+    for c in &plan.chains {
+        rows.push(vec![
+            json!("redundant nesting"),
+            json!(c.leaf.id),
+            json!(c.leaf.name),
+            json!(c.parent.id),
+            json!(format!(
+                "fix moves it up out of containers with only one child each: {}",
+                ids(&c.chain)
+            )),
+        ]);
+    }
+    for m in &plan.merges {
+        let layout = cell(&json!(m.split.layout));
+        let outcome = match m.outcome {
+            Ok(_) => "so fix merges its children into the parent",
+            Err(collapse::Kept::Chains) => {
+                "but fix leaves it for the next run, since it moves windows in or out of it first"
+            }
+            Err(collapse::Kept::NoAnchor) => {
+                "but fix can't merge it, with no window beside it to line its children up against"
+            }
+        };
+        rows.push(vec![
+            json!("same orientation"),
+            json!(m.split.id),
+            json!(m.split.name),
+            json!(m.parent.id),
+            json!(format!("{} inside {}, {}", layout, layout, outcome)),
+        ]);
+    }
+    for n in collapse::empty(ws) {
+        rows.push(vec![
+            json!("empty"),
+            json!(n.id),
+            json!(n.name),
+            Value::Null,
+            json!("a container with nothing in it"),
+        ]);
+    }
+    Table::new(&["problem", "id", "name", "parent", "reason"], rows, opts)
+}
+
+pub(crate) async fn run(
+    ctx: &mut Ctx<'_>,
+    target: PrintTarget,
+    opts: &PrintOptions,
+) -> anyhow::Result<()> {
+    if opts.watch {
+        return watch(ctx, target, opts).await;
+    }
+    for line in render(ctx, target, opts).await? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Redraw the target every time a window, workspace or binding event comes in, highlighting the
+/// lines that weren't there last time
+async fn watch(ctx: &mut Ctx<'_>, target: PrintTarget, opts: &PrintOptions) -> anyhow::Result<()> {
+    if ctx.in_listener() {
+        bail!("print --watch never finishes, so can only be run from the command line");
+    }
+    let mut i3 = I3::connect().await.context("Connect event socket")?;
+    i3.subscribe([Subscribe::Window, Subscribe::Workspace, Subscribe::Binding])
+        .await
+        .context("Subscribe to events for print --watch")?;
+    let mut events = i3.listen();
+
+    let mut last: Option<HashSet<String>> = None;
+    loop {
+        let lines = render(ctx, target, opts).await?;
+        // clear the screen and go back to the top
+        print!("\x1b[2J\x1b[H");
+        for line in lines.iter() {
+            match &last {
+                Some(last) if !last.contains(line) => println!("\x1b[7m{}\x1b[0m", line),
+                _ => println!("{}", line),
+            }
         }
-        if self.moveto {
-            write!(out, "{:?}", s.m).unwrap();
+        last = Some(lines.into_iter().collect());
+
+        match events.next().await {
+            Some(Ok(_)) => (),
+            Some(Err(e)) => return Err(e).context("Event connection for print --watch"),
+            None => bail!("i3 closed the event connection"),
         }
-        out
     }
 }
 
-impl Default for StepFormatter {
-    fn default() -> Self {
-        Self::new()
+/// The lines to print for a target
+async fn render(
+    ctx: &mut Ctx<'_>,
+    target: PrintTarget,
+    opts: &PrintOptions,
+) -> anyhow::Result<Vec<String>> {
+    if !target.containers() && !opts.fields.is_empty() {
+        bail!("--fields picks what to print about containers, so only works with tree, rects, window and all");
     }
+    if !target.containers() && opts.format.drawn() {
+        bail!("Only containers from the tree can be drawn");
+    }
+
+    let tree = ctx.fresh_tree().await.context("Get tree for Print")?;
+    let (to_print, preset) = match target {
+        PrintTarget::Tree => (tree.get_current_workspace()?, STD),
+        PrintTarget::Rects => (tree.get_current_output()?, RECT),
+        PrintTarget::Window => (tree.get_current_window()?, WINDOW),
+        PrintTarget::All => (&tree, STD),
+        PrintTarget::Workspaces => {
+            return Ok(workspaces(ctx, &tree, opts).await?.render(opts.format))
+        }
+        PrintTarget::Outputs => return Ok(outputs(ctx, opts).await?.render(opts.format)),
+        PrintTarget::Marks => return Ok(marks(&tree, opts).render(opts.format)),
+        PrintTarget::Lint => {
+            return Ok(lint(tree.get_current_workspace()?, opts).render(opts.format))
+        }
+    };
+    let fields: Vec<Field> = match (opts.fields.is_empty(), opts.format) {
+        (false, _) => opts.fields.clone(),
+        (true, Format::Text) => preset.to_vec(),
+        // the drawing already shows where each container is
+        (true, f) if f.drawn() => preset
+            .iter()
+            .copied()
+            .filter(|f| !matches!(f, Field::Depth | Field::Move))
+            .collect(),
+        (true, _) => ALL.to_vec(),
+    };
+
+    Ok(match opts.format {
+        Format::Dot => dot(to_print, &fields, opts),
+        Format::Ascii => ascii(to_print, &fields, opts),
+        f => Table::containers(to_print, &fields, opts).render(f),
+    })
 }
 
-fn pretty_print(n: &Node, fmt: &StepFormatter) {
-    println!("Tree:");
+/// The fields of a container on one line, leaving out empty ones. Windows have a layout too, but
+/// it only means something for containers that split.
+fn label(s: &Step, fields: &[Field], opts: &PrintOptions) -> String {
+    let parts: Vec<_> = fields
+        .iter()
+        .map(|f| match f {
+            Field::Layout if s.n.nodes.is_empty() => String::new(),
+            Field::Layout => format!("[{}]", f.text(s, opts)),
+            _ => f.text(s, opts),
+        })
+        .filter(|t| !t.is_empty())
+        .collect();
+    parts.join(" ")
+}
+
+/// The tree as a Graphviz graph. Containers are filled by their layout, the focused window
+/// is outlined in red, and the edge to the child each container would focus is bold.
+fn dot(n: &Node, fields: &[Field], opts: &PrintOptions) -> Vec<String> {
+    let mut lines = vec![
+        "digraph tree {".to_string(),
+        "    node [shape=box, style=\"rounded,filled\", fillcolor=white];".to_string(),
+    ];
+    // the most recent container seen at each depth, which is the parent of the next one deeper
+    let mut parents: Vec<&Node> = Vec::new();
     for s in n.preorder() {
-        println!("{}", fmt.format(&s));
+        parents.truncate(s.d);
+        let fill = match (s.n.nodes.is_empty(), s.n.layout) {
+            (true, _) => "white",
+            (false, NodeLayout::SplitH) => "lightblue",
+            (false, NodeLayout::SplitV) => "palegreen",
+            (false, NodeLayout::Stacked | NodeLayout::Tabbed) => "lightyellow",
+            (false, _) => "lightgrey",
+        };
+        let focus = match s.n.focused {
+            true => ", color=red, penwidth=3",
+            false => "",
+        };
+        lines.push(format!(
+            "    n{} [label=\"{}\", fillcolor={}{}];",
+            s.n.id,
+            escape(&label(&s, fields, opts)),
+            fill,
+            focus
+        ));
+        if let Some(parent) = parents.last() {
+            let style = match parent.focus.first() == Some(&s.n.id) {
+                true => " [penwidth=3]",
+                false => "",
+            };
+            lines.push(format!("    n{} -> n{}{};", parent.id, s.n.id, style));
+        }
+        parents.push(s.n);
     }
+    lines.push("}".to_string());
+    lines
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
 }
 
-#[allow(dead_code)]
-fn post_print(n: &Node, fmt: &StepFormatter) {
-    println!("Tree:");
-    for s in n.postorder() {
-        println!("{}", fmt.format(&s));
+/// The tree with box-drawing lines joining each container to its parent
+fn ascii(n: &Node, fields: &[Field], opts: &PrintOptions) -> Vec<String> {
+    let steps: Vec<Step> = n.preorder().collect();
+    let mut lines = Vec::new();
+    // whether the container at each depth above the current one has siblings still to come,
+    // which is when its line carries on down past the current one
+    let mut open: Vec<bool> = Vec::new();
+    for (i, s) in steps.iter().enumerate() {
+        // the next container at this depth or above is a sibling only if it's at this depth
+        let more = steps[i + 1..]
+            .iter()
+            .find(|next| next.d <= s.d)
+            .is_some_and(|next| next.d == s.d);
+        open.truncate(s.d);
+        let mut line = String::new();
+        if s.d > 0 {
+            for carries_on in &open[1..] {
+                line.push_str(if *carries_on { "│   " } else { "    " });
+            }
+            line.push_str(if more { "├── " } else { "└── " });
+        }
+        line.push_str(&label(s, fields, opts));
+        lines.push(line.trim_end().to_string());
+        open.push(more);
     }
+    lines
 }
@@ -0,0 +1,453 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, oneshot, Mutex},
+};
+use tokio_i3ipc::{
+    event::{BindingData, Event, ShutdownChange, Subscribe, TickData, WindowChange, WindowData},
+    I3,
+};
+use tokio_stream::StreamExt;
+
+use log::*;
+
+use crate::{
+    cache::TreeCache,
+    config::Config,
+    context::{Ctx, ErrorPolicy},
+    control::{Client, ControlSocket, Request},
+    ext::split_commands,
+    history::History,
+    parse_action_string, parse_command_string,
+    rules::{self, Rules},
+    Action, Cmd,
+};
+
+/// Tick payloads starting with this are i3-valet actions, e.g.
+/// `i3-msg -t send_tick 'i3-valet: layout main swap'`
+const TICK_PREFIX: &str = "i3-valet:";
+
+/// State the listener keeps for as long as it runs, including across i3 restarts.
+#[derive(Default)]
+struct State {
+    policy: ErrorPolicy,
+    cache: Mutex<TreeCache>,
+    history: Mutex<History>,
+    /// Replaced when the config is reloaded. Each job takes the config as it is when the job
+    /// starts.
+    config: Mutex<Arc<Config>>,
+    /// Bumped every time i3 restarts, so connections made to the old i3 can be replaced.
+    generation: AtomicUsize,
+}
+
+impl State {
+    async fn restarted(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.cache.lock().await.invalidate();
+    }
+}
+
+/// The connection the listener sends commands over. It is shared by every action the listener
+/// runs, so a keypress doesn't pay for a fresh connect.
+///
+/// The connection is made the first time it is needed, and is thrown away whenever an action
+/// fails with an io error, or i3 restarts, so the next action reconnects.
+#[derive(Default)]
+struct CommandConn(Option<(usize, I3)>);
+
+impl CommandConn {
+    async fn dispatch(
+        &mut self,
+        work: &Work,
+        policy: Option<ErrorPolicy>,
+        state: &State,
+    ) -> anyhow::Result<Option<Vec<String>>> {
+        let generation = state.generation.load(Ordering::SeqCst);
+        let conn = match self.0 {
+            Some((g, ref mut conn)) if g == generation => conn,
+            _ => {
+                debug!("Connecting command socket");
+                let conn = I3::connect().await.context("Connect command socket")?;
+                &mut self.0.insert((generation, conn)).1
+            }
+        };
+
+        let config = state.config.lock().await.clone();
+        let mut ctx = Ctx::new(conn, &config)
+            .with_policy(policy.unwrap_or(state.policy))
+            .with_cache(&state.cache)
+            .with_history(&state.history);
+        let res = match work {
+            Work::Action(action) => action.dispatch(&mut ctx).await.map(|_| None),
+            Work::DryRun(action) => action.commands(&mut ctx).await.map(Some),
+            Work::Rule { window, steps } => {
+                rules::run(&mut ctx, *window, steps).await.map(|_| None)
+            }
+        };
+        if let Err(ref e) = res {
+            if e.chain().any(|c| c.is::<std::io::Error>()) {
+                warn!("Dropping command connection after io error");
+                self.0 = None;
+            }
+        }
+        res
+    }
+}
+
+/// What a job does: run an action, work out the commands an action would send without sending
+/// them, or run a rule's steps on the window that matched it
+#[derive(Debug, PartialEq, Eq)]
+enum Work {
+    Action(Action),
+    DryRun(Action),
+    Rule { window: usize, steps: Vec<Cmd> },
+}
+
+/// Work waiting its turn, along with the text it was parsed from for logging, and where to send
+/// the result if anyone is waiting on it. A job sent by `i3-valet run --on-error` carries its own
+/// error policy. The result holds the commands when the job is a dry run.
+struct Job {
+    work: Work,
+    policy: Option<ErrorPolicy>,
+    source: String,
+    done: Option<oneshot::Sender<anyhow::Result<Option<Vec<String>>>>>,
+}
+
+impl Job {
+    fn finish(self, res: anyhow::Result<Option<Vec<String>>>) {
+        match self.done {
+            Some(done) => {
+                let _ = done.send(res);
+            }
+            None => {
+                if let Err(e) = res {
+                    warn!("Error running action '{}': {:#}", self.source, e);
+                }
+            }
+        }
+    }
+}
+
+/// A queue that runs actions one at a time, in the order they arrived.
+///
+/// There is a single queue for all workspaces, so every action sees the tree that the one before
+/// it left behind. When `coalesce` is set, repeats of an action that pile up behind it while it
+/// waits are dropped rather than run again.
+struct ActionQueue {
+    tx: mpsc::UnboundedSender<Job>,
+}
+
+impl ActionQueue {
+    fn start(coalesce: bool, state: Arc<State>) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_queue(rx, coalesce, state));
+        ActionQueue { tx }
+    }
+
+    fn push(&self, work: Work, source: &str) {
+        self.send(Job {
+            work,
+            policy: None,
+            source: source.to_string(),
+            done: None,
+        });
+    }
+
+    /// Queue work and get back a channel that will have its result
+    fn push_waiting(
+        &self,
+        work: Work,
+        policy: Option<ErrorPolicy>,
+        source: &str,
+    ) -> oneshot::Receiver<anyhow::Result<Option<Vec<String>>>> {
+        let (done, rx) = oneshot::channel();
+        self.send(Job {
+            work,
+            policy,
+            source: source.to_string(),
+            done: Some(done),
+        });
+        rx
+    }
+
+    fn send(&self, job: Job) {
+        if let Err(e) = self.tx.send(job) {
+            error!("Action queue has stopped, dropping: {}", e.0.source);
+        }
+    }
+}
+
+async fn run_queue(mut rx: mpsc::UnboundedReceiver<Job>, coalesce: bool, state: Arc<State>) {
+    let mut conn = CommandConn::default();
+    let mut next = None;
+    loop {
+        let job = match next.take() {
+            Some(job) => job,
+            None => match rx.recv().await {
+                Some(job) => job,
+                None => break,
+            },
+        };
+
+        // A dry run isn't coalesced, since whoever sent it is waiting on its commands
+        if coalesce && !matches!(job.work, Work::DryRun(_)) {
+            while let Ok(queued) = rx.try_recv() {
+                if queued.work != job.work || queued.policy != job.policy {
+                    next = Some(queued);
+                    break;
+                }
+                debug!("Coalescing queued action: {}", queued.source);
+                queued.finish(Ok(None));
+            }
+        }
+
+        let res = conn.dispatch(&job.work, job.policy, &state).await;
+        debug!("Action completed: {}", job.source);
+        job.finish(res);
+    }
+}
+
+/// Queue up the i3-valet actions in a binding. Returns false if the binding also has plain i3
+/// commands, which may have changed the tree in ways i3 sends no event for.
+fn handle_binding_event(e: BindingData, queue: &ActionQueue) -> bool {
+    trace!("Binding event: {:?}", e);
+    let mut only_valet = true;
+    for subcmd in split_commands(&e.binding.command) {
+        match parse_command_string(subcmd) {
+            Ok(Some(cmd)) => {
+                queue.push(Work::Action(cmd.action), subcmd);
+            }
+            Ok(None) => {
+                debug!("Skipping non-i3-valet action: {}", subcmd);
+                only_valet = false;
+            }
+
+            Err(e) => {
+                warn!("Error parsing action '{}': {:#}", subcmd, e);
+            }
+        };
+    }
+    only_valet
+}
+
+/// Queue up the actions in a tick payload meant for i3-valet. Several actions can be given,
+/// separated by `;` like in a binding, but without the `nop`.
+fn handle_tick_event(e: TickData, queue: &ActionQueue) {
+    trace!("Tick event: {:?}", e);
+    let Some(actions) = e.payload.strip_prefix(TICK_PREFIX) else {
+        debug!("Skipping tick not meant for i3-valet: {}", e.payload);
+        return;
+    };
+    for subcmd in split_commands(actions) {
+        match parse_action_string(subcmd) {
+            Ok(cmd) => queue.push(Work::Action(cmd.action), subcmd),
+            Err(e) => warn!("Error parsing action '{}': {:#}", subcmd, e),
+        }
+    }
+}
+
+/// Queue up the rules that match a new or retitled window
+fn handle_window_event(e: &WindowData, queue: &ActionQueue, rules: &Rules) {
+    for rule in rules.matching(e.change, &e.container) {
+        debug!("Window {} matched rule: {:?}", e.container.id, rule);
+        queue.push(
+            Work::Rule {
+                window: e.container.id,
+                steps: rule.steps().to_vec(),
+            },
+            &format!("rule for window {}", e.container.id),
+        );
+    }
+}
+
+async fn serve_control(socket: ControlSocket, queue: Arc<ActionQueue>) {
+    loop {
+        match socket.accept().await {
+            Ok(client) => {
+                tokio::spawn(handle_control(client, queue.clone()));
+            }
+            Err(e) => warn!("Error accepting on control socket: {}", e),
+        }
+    }
+}
+
+async fn handle_control(client: Client, queue: Arc<ActionQueue>) {
+    let req = match client.request().await {
+        Ok(req) => req,
+        Err(e) => {
+            warn!("Error reading from control socket: {}", e);
+            return;
+        }
+    };
+    let work = match req.dry_run {
+        true => Work::DryRun(req.action.clone()),
+        false => Work::Action(req.action.clone()),
+    };
+    let done = queue.push_waiting(work, req.policy, "control socket");
+    reply_control(req, done).await
+}
+
+async fn reply_control(
+    req: Request,
+    done: oneshot::Receiver<anyhow::Result<Option<Vec<String>>>>,
+) {
+    let res = done
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Listener dropped the action")));
+    if let Err(e) = req.reply(res.into()).await {
+        warn!("Error replying on control socket: {}", e);
+    }
+}
+
+/// Connect to i3 and subscribe to the events the listener needs
+async fn subscribe() -> std::io::Result<I3> {
+    let mut i3 = I3::connect().await?;
+    i3.subscribe([
+        Subscribe::Binding,
+        Subscribe::Window,
+        Subscribe::Workspace,
+        Subscribe::Output,
+        Subscribe::Mode,
+        Subscribe::Shutdown,
+        Subscribe::Tick,
+    ])
+    .await?;
+    Ok(i3)
+}
+
+/// Reconnect to i3 after it restarts, backing off between attempts while i3 comes back up.
+async fn resubscribe() -> anyhow::Result<I3> {
+    let mut delay = Duration::from_millis(100);
+    let mut attempts = 0;
+    loop {
+        tokio::time::sleep(delay).await;
+        match subscribe().await {
+            Ok(i3) => break Ok(i3),
+            Err(e) if attempts < RECONNECT_ATTEMPTS => {
+                debug!("Reconnect to i3 failed, retrying in {:?}: {}", delay, e);
+                attempts += 1;
+                delay = (delay * 2).min(Duration::from_secs(2));
+            }
+            Err(e) => break Err(e).context("i3 did not come back after restarting"),
+        }
+    }
+}
+
+const RECONNECT_ATTEMPTS: usize = 15;
+
+/// Handle events until i3 goes away. Returns true if i3 is restarting, and false if it is
+/// exiting.
+async fn handle_events(i3: I3, queue: &ActionQueue, state: &State) -> bool {
+    let mut listener = i3.listen();
+    while let Some(event) = listener.next().await {
+        let evt = match event {
+            Ok(evt) => evt,
+            Err(e) => {
+                warn!("Event connection died, assuming i3 is restarting: {}", e);
+                return true;
+            }
+        };
+        match evt {
+            Event::Binding(ev) => {
+                let only_valet = handle_binding_event(ev, queue);
+                if !only_valet {
+                    state.cache.lock().await.invalidate();
+                }
+            }
+            Event::Window(ref ev) => {
+                match (ev.change, ev.container.window) {
+                    (WindowChange::Focus, Some(w)) => state.history.lock().await.focused(w),
+                    (WindowChange::Close, Some(w)) => state.history.lock().await.closed(w),
+                    _ => (),
+                }
+                state.cache.lock().await.apply(&evt);
+                let config = state.config.lock().await.clone();
+                handle_window_event(ev, queue, &config.rules);
+            }
+            Event::Workspace(_) | Event::Output(_) => {
+                state.cache.lock().await.apply(&evt);
+            }
+            Event::Mode(_) => state.history.lock().await.commit(),
+            Event::Tick(ev) => handle_tick_event(ev, queue),
+            Event::Shutdown(ev) => return ev.change == ShutdownChange::Restart,
+            _ => (),
+        }
+    }
+    warn!("Event connection closed, assuming i3 is restarting");
+    true
+}
+
+/// Load the config again each time the listener gets SIGHUP. If the new config has errors the
+/// old one is kept.
+async fn reload_config(path: Option<PathBuf>, state: Arc<State>) -> anyhow::Result<()> {
+    let mut hangups = signal(SignalKind::hangup()).context("Listening for SIGHUP")?;
+    while hangups.recv().await.is_some() {
+        match Config::find(path.as_deref()) {
+            Ok(config) => {
+                info!("Reloaded config");
+                *state.config.lock().await = Arc::new(config);
+            }
+            Err(e) => error!("Keeping the old config, error reloading: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Run the listener. `config_path` is where `config` came from, if it was given on the command
+/// line, so it can be reloaded from the same place.
+pub async fn listener(
+    coalesce: bool,
+    policy: ErrorPolicy,
+    config: Config,
+    config_path: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    // A second listener would run every binding again, so bail out if one is already running.
+    // Anything else stopping the socket only costs `i3-valet run` its shortcut to the listener.
+    let socket = match ControlSocket::bind().await {
+        Ok(socket) => Some(socket),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            return Err(e).context("i3-valet listen is already running")
+        }
+        Err(e) => {
+            warn!("Not accepting actions from i3-valet run: {}", e);
+            None
+        }
+    };
+
+    let mut i3 = subscribe().await.context("init listener")?;
+
+    let state = Arc::new(State {
+        policy,
+        config: Mutex::new(Arc::new(config)),
+        ..Default::default()
+    });
+    let queue = Arc::new(ActionQueue::start(coalesce, state.clone()));
+
+    let reload = reload_config(config_path, state.clone());
+    tokio::spawn(async {
+        if let Err(e) = reload.await {
+            warn!("Not reloading config on SIGHUP: {:#}", e);
+        }
+    });
+
+    if let Some(socket) = socket {
+        tokio::spawn(serve_control(socket, queue.clone()));
+    }
+
+    while handle_events(i3, &queue, &state).await {
+        info!("i3 is restarting, reconnecting");
+        state.restarted().await;
+        i3 = resubscribe().await?;
+    }
+    info!("i3 is exiting");
+    Ok(())
+}
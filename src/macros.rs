@@ -0,0 +1,108 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use anyhow::{anyhow, bail, Context};
+use log::*;
+use serde::Deserialize;
+
+use crate::{config::Config, context::Ctx, send_commands, Action, Cmd};
+
+/// The named macros from the `[macro]` table in the config:
+///
+/// ```toml
+/// [macro]
+/// new-main = ["nop workspace move-new", "nop layout main set"]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Macros {
+    steps: BTreeMap<String, Vec<String>>,
+    #[serde(skip)]
+    parsed: BTreeMap<String, Vec<Cmd>>,
+}
+
+impl Macros {
+    /// Parse the steps of every macro, so mistakes show up when the config is loaded rather than
+    /// when the macro is run
+    pub fn prepare(&mut self) -> anyhow::Result<()> {
+        for (name, steps) in self.steps.iter() {
+            let cmds = parse_steps(steps, &format!("macro.{}", name))?;
+            self.parsed.insert(name.clone(), cmds);
+        }
+        Ok(())
+    }
+
+    /// Check that every i3-valet action in the macros is a built-in action or an alias that
+    /// isn't for a macro. Anything not built in parses as an alias, so this can only be done once
+    /// the aliases are loaded.
+    pub(crate) fn check_actions(&self, config: &Config) -> anyhow::Result<()> {
+        for (name, steps) in self.parsed.iter() {
+            for (i, step) in steps.iter().enumerate() {
+                let Cmd::Valet(action) = step else {
+                    continue;
+                };
+                let key = || format!("macro.{}[{}]", name, i);
+                if let Action::Macro { .. } = config.resolve(action).with_context(key)?.as_ref() {
+                    bail!("{}: macros can't run other macros", key());
+                }
+                config.check(action).with_context(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&[Cmd]> {
+        self.parsed.get(name).map(Vec::as_slice)
+    }
+}
+
+/// Parse a macro's steps. `key` says where they came from, for errors.
+fn parse_steps(steps: &[String], key: &str) -> anyhow::Result<Vec<Cmd>> {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let cmd = Cmd::parse(step).with_context(|| format!("{}[{}]", key, i))?;
+            if let Cmd::Valet(Action::Macro { .. }) = cmd {
+                bail!("{}[{}]: macros can't run other macros", key, i);
+            }
+            Ok(cmd)
+        })
+        .collect()
+}
+
+/// Run a macro, either the one from the config called `name`, or the given steps.
+///
+/// Each step is run on its own, after the one before it is done, so it sees the tree that step
+/// left behind. Unless `keep_going` is set, the first step that fails stops the macro.
+pub(crate) async fn run(
+    ctx: &mut Ctx<'_>,
+    name: Option<&str>,
+    steps: &[String],
+    keep_going: bool,
+) -> anyhow::Result<()> {
+    let config = ctx.config;
+    let steps = match name {
+        Some(name) => Cow::Borrowed(
+            config
+                .macros
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown macro: {}", name))?,
+        ),
+        None => Cow::Owned(parse_steps(steps, "--step")?),
+    };
+
+    for (i, step) in steps.iter().enumerate() {
+        let res = match step {
+            Cmd::I3(cmd) => send_commands(ctx, std::slice::from_ref(cmd)).await,
+            Cmd::Valet(action) => match config.resolve(action)?.as_ref() {
+                Action::Macro { .. } => Err(anyhow!("macros can't run other macros")),
+                _ => Box::pin(action.dispatch(ctx)).await,
+            },
+        };
+        match res.with_context(|| format!("Macro step {}", i + 1)) {
+            Err(e) if keep_going => warn!("Continuing macro after error: {:#}", e),
+            res => res?,
+        }
+    }
+    Ok(())
+}
@@ -1,19 +1,35 @@
-use anyhow::Context;
-use tokio_i3ipc::{
-    event::{BindingData, Event, Subscribe},
-    I3,
-};
-use tokio_stream::StreamExt;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use tokio_i3ipc::I3;
+
+use config::Config;
+use context::{Ctx, ErrorPolicy};
+use ext::NodeSearch;
 
 use clap::{Parser, Subcommand, ValueEnum};
 use log::*;
+use serde::{Deserialize, Serialize};
 
+pub mod cache;
 pub mod collapse;
+pub mod config;
+pub mod context;
+pub mod control;
+pub mod doctor;
 pub mod ext;
+#[cfg(test)]
+mod fixture;
 pub mod floats;
+pub mod genconfig;
+pub mod history;
 pub mod info;
+pub mod listen;
+pub mod macros;
 pub mod manage;
 pub mod output;
+pub mod rules;
+pub mod when;
 pub mod workspace;
 
 #[derive(ValueEnum, Debug, Clone, Copy)]
@@ -40,29 +56,68 @@ impl LogLevel {
     }
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 enum LayoutCmd {
     /// Set and focus (etc) a main window and auxilliary windows
     Main { action: manage::LayoutAction },
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum FocusCmd {
+    /// Focus a previously focused window
+    Back {
+        /// how many focus changes to go back
+        #[arg(default_value_t = 1)]
+        n: usize,
+        /// only go back through windows on the current workspace
+        #[arg(long)]
+        workspace: bool,
+    },
+    /// Step through recently focused windows, alt-tab style.
+    ///
+    /// The history isn't reordered until the binding mode changes, so this is meant to be bound
+    /// in a mode that is left once the right window is found.
+    Cycle {
+        dir: output::Direction,
+        /// only cycle through windows on the current workspace
+        #[arg(long)]
+        workspace: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 enum Action {
     /// clean up the window tree
-    Fix,
+    Fix {
+        /// clean up every workspace, rather than just the current one
+        #[arg(long, conflicts_with_all = ["workspace", "output"])]
+        all: bool,
+        /// clean up the workspace with this name
+        #[arg(long, conflicts_with = "output")]
+        workspace: Option<String>,
+        /// clean up every workspace on an output: next, prev or an output name
+        #[arg(long)]
+        output: Option<String>,
+        /// a pass to run, which can be given more than once. All of them by default.
+        #[arg(long = "pass")]
+        passes: Vec<collapse::Pass>,
+    },
 
     /// Move A floating window to anchor point
+    #[command(allow_missing_positional = true)]
     Loc {
-        /// Positioning of window.
-        how: floats::Positioning,
+        /// Positioning of window, `defaults.positioning` from the config if not given.
+        how: Option<floats::Positioning>,
         /// Anchor point to position window
         pos: floats::Pos,
     },
 
-    ///Print information about the current tree or window
+    ///Print information about the tree, windows, workspaces, outputs or marks
     Print {
         /// what to print
         target: info::PrintTarget,
+        #[command(flatten)]
+        opts: info::PrintOptions,
     },
 
     /// Workspace commands
@@ -84,15 +139,85 @@ enum Action {
         #[command(subcommand)]
         cmd: LayoutCmd,
     },
+
+    /// Focus windows from the listener's focus history
+    Focus {
+        #[command(subcommand)]
+        cmd: FocusCmd,
+    },
+
+    /// Run several actions and i3 commands in order, each seeing the tree the last one left.
+    ///
+    /// Steps are written like a binding: i3-valet actions start with `nop`, anything else is an
+    /// i3 command.
+    Macro {
+        /// a macro from the config
+        #[arg(required_unless_present = "steps")]
+        name: Option<String>,
+        /// a step to run, instead of a macro from the config
+        #[arg(long = "step", conflicts_with = "name")]
+        steps: Vec<String>,
+        /// run the rest of the steps after one fails, rather than stopping
+        #[arg(long)]
+        keep_going: bool,
+    },
+
+    /// Run one action or another, depending on the focused window.
+    ///
+    /// The actions are written without a `nop`, e.g.
+    /// `when floating --then "loc rel center" --else fix`
+    When {
+        /// what to check
+        test: when::Test,
+        /// what the test compares against, for every test but floating
+        value: Option<String>,
+        /// the action to run if the test passes
+        #[arg(long)]
+        then: Option<String>,
+        /// the action to run if it doesn't
+        #[arg(long = "else")]
+        otherwise: Option<String>,
+    },
+
+    /// An alias from the config, followed by any extra arguments for it
+    #[command(external_subcommand)]
+    Alias(Vec<String>),
 }
 
 #[derive(Subcommand, Debug)]
 enum RunType {
     /// process keybinding events for i3-valet actions to take
-    Listen,
+    Listen {
+        /// drop queued actions that are identical to the one about to run
+        #[arg(long)]
+        coalesce: bool,
+    },
     /// run a specific action.
-    #[command(subcommand)]
-    Run(Action),
+    Run {
+        /// print the i3 commands the action would send, instead of sending them
+        #[arg(long, global = true)]
+        dry_run: bool,
+        /// print dry run commands as a JSON array instead of one per line
+        #[arg(long, global = true, requires = "dry_run")]
+        json: bool,
+        #[command(subcommand)]
+        action: Action,
+    },
+    /// check the i3-valet actions bound in the running i3 config
+    #[command(alias = "doctor")]
+    CheckConfig,
+    /// print i3 config with bindings for i3-valet actions
+    GenConfig {
+        /// which actions to make bindings for
+        #[arg(default_value = "all")]
+        family: genconfig::Family,
+        /// which keys to use for directions and anchor points
+        #[arg(long, default_value = "vim")]
+        keys: genconfig::Keys,
+        /// the modifier for the keys that enter the modes
+        #[arg(long = "mod", default_value = "$mod")]
+        modifier: String,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -101,6 +226,13 @@ struct App {
     /// log level
     #[arg(long, default_value = "off")]
     log: LogLevel,
+    /// config file to use instead of $XDG_CONFIG_HOME/i3-valet.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// what to do when i3 rejects one of an action's commands. Actions handed to a running
+    /// listener use the listener's policy unless this is given. [default: report]
+    #[arg(long, value_enum)]
+    on_error: Option<ErrorPolicy>,
     #[command(subcommand)]
     how: RunType,
 }
@@ -113,54 +245,238 @@ struct ReceivedCmd {
 }
 
 impl Action {
-    async fn dispatch(&self, conn: &mut I3) -> anyhow::Result<()> {
-        info!("Dispatching: {:?}", self);
-        let cmds = match self {
-            Action::Fix => {
-                let tree = conn.get_tree().await.context("Get tree for Fix")?;
-                collapse::clean_current_workspace(&tree)?
+    /// Actions that write their results to stdout have to run in this process, rather than being
+    /// handed to a running listener.
+    fn runs_locally(&self) -> bool {
+        matches!(self, Action::Print { .. })
+    }
+
+    /// Work out the i3 commands that carry out the action
+    async fn commands(&self, ctx: &mut Ctx<'_>) -> anyhow::Result<Vec<String>> {
+        let config = ctx.config;
+        Ok(match config.resolve(self)?.as_ref() {
+            Action::Fix {
+                all,
+                workspace,
+                output,
+                passes,
+            } => {
+                // sizes of tiled windows change without i3 sending an event, so the cache can't
+                // be trusted for them
+                let tree = ctx.fresh_tree().await.context("Get tree for Fix")?;
+                let output = match output
+                    .as_deref()
+                    .map(|o| (o, output::Direction::from_str(o, false)))
+                {
+                    Some((_, Ok(dir))) => {
+                        let workspaces = ctx
+                            .conn
+                            .get_workspaces()
+                            .await
+                            .context("Get workspaces for Fix")?;
+                        let outputs = ctx
+                            .conn
+                            .get_outputs()
+                            .await
+                            .context("Get outputs for Fix")?;
+                        Some(output::neighbor(dir, &workspaces, &outputs)?)
+                    }
+                    Some((name, Err(_))) => Some(name.to_string()),
+                    None => None,
+                };
+                let scope = match (all, workspace, &output) {
+                    (true, _, _) => collapse::Scope::All,
+                    (_, Some(name), _) => collapse::Scope::Workspace(name),
+                    (_, _, Some(name)) => collapse::Scope::Output(name),
+                    _ => collapse::Scope::Current,
+                };
+                collapse::clean(&tree, scope, passes)?
             }
             Action::Loc { pos, how } => {
-                let tree = conn.get_tree().await.context("Get tree for Loc")?;
-                floats::teleport_float(&tree, *pos, *how)?
+                let tree = ctx.fresh_tree().await.context("Get tree for Loc")?;
+                let how = how.unwrap_or(config.defaults.positioning);
+                floats::teleport_float(&tree, *pos, how)?
             }
-            Action::Print { target } => {
-                let tree = conn.get_tree().await.context("Get tree for Print")?;
-                info::run(*target, &tree).map(|_| vec![])?
+            Action::Print { target, opts } => {
+                info::run(ctx, *target, opts).await.map(|_| vec![])?
             }
             Action::Workspace { target } => {
-                let mut workspaces = conn
+                let mut workspaces = ctx
+                    .conn
                     .get_workspaces()
                     .await
                     .context("Get workspaces for Workspace")?;
-                workspace::run(*target, &mut workspaces)
+                workspace::run(*target, &mut workspaces, config.defaults.first_workspace)
             }
             Action::Output { change, dir } => {
-                let workspaces = conn
+                let workspaces = ctx
+                    .conn
                     .get_workspaces()
                     .await
                     .context("Get workspaces for Ouput")?;
 
-                let outputs = conn.get_outputs().await.context("Get outputs for Output")?;
+                let outputs = ctx
+                    .conn
+                    .get_outputs()
+                    .await
+                    .context("Get outputs for Output")?;
                 output::run(*change, *dir, &workspaces, &outputs)?
             }
             Action::Layout { cmd } => match cmd {
                 LayoutCmd::Main { action } => {
-                    let tree = conn.get_tree().await.context("Get tree for Layout")?;
-                    manage::run_main(*action, &tree)?
+                    let tree = ctx.tree().await.context("Get tree for Layout")?;
+                    let recent = match ctx.history().await {
+                        Ok(history) => history
+                            .recent(tree.get_current_workspace()?)
+                            .into_iter()
+                            .map(|n| n.id)
+                            .collect(),
+                        Err(_) => vec![],
+                    };
+                    manage::run_main(*action, &tree, &recent, &config.defaults.mark_format)?
                 }
             },
-        };
+            Action::Focus { cmd } => {
+                let tree = ctx.tree().await.context("Get tree for Focus")?;
+                let mut history = ctx.history().await?;
+                match cmd {
+                    FocusCmd::Back { n, workspace } => {
+                        history::focus_back(&tree, &history, *n, *workspace)?
+                    }
+                    FocusCmd::Cycle { dir, workspace } => {
+                        history::focus_cycle(&tree, &mut history, *dir, *workspace)?
+                    }
+                }
+            }
+            Action::Macro { .. } => {
+                bail!(
+                    "A macro's steps depend on the ones before them, so can't be worked out ahead"
+                )
+            }
+            Action::When {
+                test,
+                value,
+                then,
+                otherwise,
+            } => {
+                let branch = when::choose(
+                    ctx,
+                    *test,
+                    value.as_deref(),
+                    then.as_deref(),
+                    otherwise.as_deref(),
+                )
+                .await?;
+                match branch {
+                    Some(action) => Box::pin(action.commands(ctx)).await?,
+                    None => vec![],
+                }
+            }
+            Action::Alias(args) => bail!("Alias {} stands for another alias", args[0]),
+        })
+    }
 
-        for cmd in cmds {
-            ext::i3_command(&cmd, conn)
-                .await
-                .context(format!("Running command {}", cmd))?;
+    /// Carry out the action. This used to take just the connection, but actions now need the
+    /// config, error policy, and the listener's cache and history too, which all come in `ctx`.
+    async fn dispatch(&self, ctx: &mut Ctx<'_>) -> anyhow::Result<()> {
+        info!("Dispatching: {:?}", self);
+        let config = ctx.config;
+        match config.resolve(self)?.as_ref() {
+            Action::Macro {
+                name,
+                steps,
+                keep_going,
+            } => return macros::run(ctx, name.as_deref(), steps, *keep_going).await,
+            Action::When {
+                test,
+                value,
+                then,
+                otherwise,
+            } => {
+                let branch = when::choose(
+                    ctx,
+                    *test,
+                    value.as_deref(),
+                    then.as_deref(),
+                    otherwise.as_deref(),
+                )
+                .await?;
+                return match branch {
+                    Some(action) => Box::pin(action.dispatch(ctx)).await,
+                    None => Ok(()),
+                };
+            }
+            _ => (),
+        }
+
+        let cmds = self.commands(ctx).await?;
+
+        if cmds.is_empty() {
+            return Ok(());
+        }
+
+        send_commands(ctx, &cmds).await
+    }
+}
+
+/// Something to run on behalf of a binding or rule: an i3-valet action, or a plain i3 command
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cmd {
+    Valet(Action),
+    I3(String),
+}
+
+impl Cmd {
+    /// Parse a single sub-command the way it is written in a binding, where i3-valet actions
+    /// follow a `nop`
+    fn parse(cmd: &str) -> anyhow::Result<Self> {
+        Ok(match parse_command_string(cmd)? {
+            Some(received) => Cmd::Valet(received.action),
+            None => Cmd::I3(cmd.trim().to_string()),
+        })
+    }
+}
+
+/// Send commands to i3, handling any that are rejected according to the error policy
+async fn send_commands(ctx: &mut Ctx<'_>, cmds: &[String]) -> anyhow::Result<()> {
+    ctx.invalidate().await;
+    if ctx.policy == ErrorPolicy::Stop {
+        return send_until_rejected(ctx, cmds).await;
+    }
+    let rejected = ext::i3_batch(cmds, ctx.conn)
+        .await
+        .context("Running commands")?;
+    if rejected.is_empty() {
+        return Ok(());
+    }
+    match ctx.policy {
+        ErrorPolicy::Report | ErrorPolicy::Stop => {
+            let errors: Vec<_> = rejected.iter().map(|e| e.to_string()).collect();
+            bail!("{}", errors.join("\n"))
+        }
+        ErrorPolicy::Ignore => {
+            for e in rejected {
+                warn!("Ignoring failed command: {}", e);
+            }
+            Ok(())
         }
-        Ok(())
     }
 }
 
+/// Send commands to i3 one at a time, failing at the first one that is rejected. Commands joined
+/// by `,` share their criteria, so they go together.
+async fn send_until_rejected(ctx: &mut Ctx<'_>, cmds: &[String]) -> anyhow::Result<()> {
+    for cmd in cmds.iter().flat_map(|c| ext::split_criteria_scopes(c)) {
+        let rejected = ext::i3_batch(&[cmd.trim().to_string()], ctx.conn)
+            .await
+            .context("Running commands")?;
+        if let Some(e) = rejected.first() {
+            bail!("{}; the commands after it weren't sent", e);
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), String> {
     let app = App::parse();
@@ -171,16 +487,83 @@ async fn main() -> Result<(), String> {
 
     info!("Welcome to i3-valet");
 
+    let config = match Config::find(app.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Fatal error loading config: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
     match app.how {
-        RunType::Listen => {
-            if let Err(e) = listener().await {
-                error!("Fatal error running command: {}", e);
+        RunType::Listen { coalesce } => {
+            if let Err(e) = listen::listener(
+                coalesce,
+                app.on_error.unwrap_or_default(),
+                config,
+                app.config,
+            )
+            .await
+            {
+                eprintln!("Fatal error running listener: {:#}", e);
                 std::process::exit(1);
             }
         }
-        RunType::Run(a) => {
+        RunType::CheckConfig => match doctor::check_config(&config).await {
+            Ok(true) => (),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Fatal error checking config: {:#}", e);
+                std::process::exit(1);
+            }
+        },
+        RunType::GenConfig {
+            family,
+            keys,
+            modifier,
+        } => print!("{}", genconfig::generate(family, keys, &modifier)),
+        RunType::Run {
+            action: a,
+            dry_run,
+            json,
+        } => {
+            let a = match config.resolve(&a) {
+                Ok(a) => a.into_owned(),
+                Err(e) => {
+                    eprintln!("Fatal error running command: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            if !a.runs_locally() {
+                match control::send(&a, app.on_error, dry_run).await {
+                    Some(Ok(control::Reply::Done)) => return Ok(()),
+                    Some(Ok(control::Reply::Commands(cmds))) => {
+                        print_commands(&cmds, json);
+                        return Ok(());
+                    }
+                    Some(Ok(control::Reply::Failed(e))) => {
+                        eprintln!("Fatal error running command: {}", e);
+                        std::process::exit(1);
+                    }
+                    Some(Err(e)) => {
+                        eprintln!("Fatal error talking to listener: {}", e);
+                        std::process::exit(1);
+                    }
+                    None => debug!("No listener running, running action directly"),
+                }
+            }
+
             let mut conn = I3::connect().await.expect("i3connect");
-            if let Err(e) = a.dispatch(&mut conn).await {
+            let mut ctx =
+                Ctx::new(&mut conn, &config).with_policy(app.on_error.unwrap_or_default());
+            let res = match dry_run {
+                true => a
+                    .commands(&mut ctx)
+                    .await
+                    .map(|cmds| print_commands(&cmds, json)),
+                false => a.dispatch(&mut ctx).await,
+            };
+            if let Err(e) = res {
                 eprintln!("Fatal error running command: {:#}", e);
                 std::process::exit(1);
             }
@@ -191,57 +574,101 @@ async fn main() -> Result<(), String> {
     Ok(())
 }
 
+fn print_commands(cmds: &[String], json: bool) {
+    if json {
+        println!("{}", serde_json::to_string(cmds).unwrap());
+    } else {
+        for cmd in cmds {
+            println!("{}", cmd);
+        }
+    }
+}
+
 fn parse_command_string(action: &str) -> anyhow::Result<Option<ReceivedCmd>> {
     debug!("parsing command: {}", action);
-    let mut args = action.split_whitespace();
-    Ok(if let Some("nop") = args.next() {
-        ReceivedCmd::try_parse_from(args).map(Some)?
-    } else {
-        None
+    Ok(match ext::nop_argument(action) {
+        Some(arg) => Some(ReceivedCmd::try_parse_from(ext::split_words(&arg))?),
+        None => None,
     })
 }
 
-async fn handle_binding_event(e: BindingData) {
-    trace!("Binding event: {:?}", e);
-    for subcmd in e.binding.command.split(';') {
-        match parse_command_string(subcmd) {
-            Ok(Some(cmd)) => {
-                let mut conn = match I3::connect().await {
-                    Ok(conn) => conn,
-                    Err(e) => {
-                        log::error!("couldn't connect while handling binding: {}", e);
-                        return;
-                    }
-                };
-                if let Err(e) = cmd.action.dispatch(&mut conn).await {
-                    warn!("Error running action '{}': {:#}", subcmd, e);
-                }
-            }
-            Ok(None) => {
-                debug!("Skipping non-i3-valet action: {}", subcmd);
-            }
+/// Parse an action written without the leading `nop`, as it is in tick payloads
+fn parse_action_string(action: &str) -> anyhow::Result<ReceivedCmd> {
+    debug!("parsing action: {}", action);
+    Ok(ReceivedCmd::try_parse_from(ext::split_words(action))?)
+}
 
-            Err(e) => {
-                warn!("Error parsing action '{}': {:#}", subcmd, e);
-            }
-        };
-        debug!("Action completed: {}", subcmd);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse each sub-command of a binding the way the listener does
+    fn parse_binding(binding: &str) -> Vec<anyhow::Result<Option<Action>>> {
+        ext::split_commands(binding)
+            .into_iter()
+            .map(|cmd| parse_command_string(cmd).map(|r| r.map(|r| r.action)))
+            .collect()
     }
-}
 
-async fn listener() -> anyhow::Result<()> {
-    let mut i3 = I3::connect().await.context("init listener")?;
+    #[test]
+    fn mixed_binding() {
+        let parsed = parse_binding(r#"nop workspace "2: web"; split h, nop fix"#);
+        assert_eq!(parsed.len(), 3);
+        // quoted, but still not a workspace action
+        assert!(parsed[0].is_err());
+        assert!(matches!(parsed[1], Ok(None)));
+        assert!(matches!(parsed[2], Ok(Some(Action::Fix { .. }))));
+    }
 
-    i3.subscribe([Subscribe::Binding])
-        .await
-        .context("couldn't subscribe")?;
+    #[test]
+    fn quoted_argument_with_separators() {
+        // i3 ends an unquoted nop argument at the first separator, quotes or not, and rejects
+        // what's left over
+        let parsed = parse_binding(r#"nop when workspace "2: web; mail" --then fix"#);
+        assert_eq!(parsed.len(), 2);
+        match &parsed[0] {
+            Ok(Some(Action::When { value, then, .. })) => {
+                assert_eq!(value.as_deref(), Some("2: web"));
+                assert_eq!(then.as_deref(), None);
+            }
+            other => panic!("expected a when action, got {:?}", other),
+        }
+        assert!(matches!(parsed[1], Ok(None)));
+        // quoting all of it keeps it together
+        let parsed =
+            parse_binding(r#"nop "when workspace \"2: web; mail\" --then fix", focus left"#);
+        assert_eq!(parsed.len(), 2);
+        match &parsed[0] {
+            Ok(Some(Action::When { value, then, .. })) => {
+                assert_eq!(value.as_deref(), Some("2: web; mail"));
+                assert_eq!(then.as_deref(), Some("fix"));
+            }
+            other => panic!("expected a when action, got {:?}", other),
+        }
+        assert!(matches!(parsed[1], Ok(None)));
+    }
 
-    let mut listener = i3.listen();
-    while let Some(event) = listener.next().await {
-        let evt = event.context("Connection died, i3 is most likey termnating")?;
-        if let Event::Binding(ev) = evt {
-            tokio::spawn(async { handle_binding_event(ev).await });
+    #[test]
+    fn repeated_pass_in_a_binding() {
+        let parsed = parse_binding("nop fix --pass chains --pass flatten");
+        assert_eq!(parsed.len(), 1);
+        match &parsed[0] {
+            Ok(Some(Action::Fix { passes, .. })) => {
+                assert_eq!(passes, &[collapse::Pass::Chains, collapse::Pass::Flatten]);
+            }
+            other => panic!("expected a fix action, got {:?}", other),
         }
     }
-    Ok(())
+
+    #[test]
+    fn only_nop_commands_are_actions() {
+        assert!(matches!(parse_command_string("fix"), Ok(None)));
+        assert!(matches!(parse_command_string("  "), Ok(None)));
+        assert!(matches!(
+            parse_command_string(" nop  loc rel se "),
+            Ok(Some(ReceivedCmd {
+                action: Action::Loc { .. }
+            }))
+        ));
+    }
 }
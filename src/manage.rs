@@ -1,9 +1,10 @@
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use tokio_i3ipc::reply::Node;
 
 use crate::ext::{NodeSearch, NotFound};
 
-#[derive(ValueEnum, Clone, Debug, Copy)]
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LayoutAction {
     /// Set the current window as the "main" window
     Set,
@@ -14,18 +15,31 @@ pub enum LayoutAction {
     Swap,
     /// Focus the main window
     Focus,
+    /// Like swap, but when main is focused, swaps with the previously focused window from the
+    /// listener's focus history instead of the window marked as last
+    SwapRecent,
 }
 
-pub fn run_main(action: LayoutAction, tree: &Node) -> Result<Vec<String>, NotFound> {
+/// `recent` is the focus history as container ids, most recent first, and may be empty when there
+/// isn't one.
+/// `marks` is the format of the mark names, see [`mark_name`].
+pub fn run_main(
+    action: LayoutAction,
+    tree: &Node,
+    recent: &[usize],
+    marks: &str,
+) -> Result<Vec<String>, NotFound> {
     match action {
-        LayoutAction::Set => make_main(tree),
-        LayoutAction::Swap => swap_main(tree),
-        LayoutAction::Focus => focus_main(tree),
+        LayoutAction::Set => make_main(tree, marks),
+        LayoutAction::Swap => swap_main(tree, marks),
+        LayoutAction::Focus => focus_main(tree, marks),
+        LayoutAction::SwapRecent => swap_main_recent(tree, recent, marks),
     }
 }
 
-fn mark_name(wsname: &str, name: &str) -> String {
-    format!("{}_{}", wsname, name)
+/// Fill in `{workspace}` and `{name}` in a mark name format
+fn mark_name(marks: &str, wsname: &str, name: &str) -> String {
+    marks.replace("{workspace}", wsname).replace("{name}", name)
 }
 
 fn unmark(target: Option<&Node>, mark: &str) -> String {
@@ -46,23 +60,23 @@ fn swap_mark(mark: &str) -> String {
     format!("swap container with mark {}", mark)
 }
 
-fn make_main(tree: &Node) -> Result<Vec<String>, NotFound> {
+fn make_main(tree: &Node, marks: &str) -> Result<Vec<String>, NotFound> {
     //let node = conn.get_tree().map_err(|_| "get_tree 1")?;
     let ws = tree.get_current_workspace()?;
 
     Ok(vec![mark(
         None,
-        &mark_name(ws.name.as_ref().unwrap(), "main"),
+        &mark_name(marks, ws.name.as_ref().unwrap(), "main"),
     )])
 }
 
-fn swap_main(tree: &Node) -> Result<Vec<String>, NotFound> {
+fn swap_main(tree: &Node, marks: &str) -> Result<Vec<String>, NotFound> {
     let ws = tree.get_current_workspace()?;
 
     let cur_window = ws.get_current_window()?;
 
-    let main_mark = mark_name(ws.name.as_ref().unwrap(), "main");
-    let last_mark = mark_name(ws.name.as_ref().unwrap(), "last");
+    let main_mark = mark_name(marks, ws.name.as_ref().unwrap(), "main");
+    let last_mark = mark_name(marks, ws.name.as_ref().unwrap(), "last");
 
     let main = ws.find_mark(&main_mark)?;
 
@@ -85,12 +99,41 @@ fn swap_main(tree: &Node) -> Result<Vec<String>, NotFound> {
     Ok(res)
 }
 
-fn focus_main(tree: &Node) -> Result<Vec<String>, NotFound> {
+fn swap_main_recent(tree: &Node, recent: &[usize], marks: &str) -> Result<Vec<String>, NotFound> {
+    let ws = tree.get_current_workspace()?;
+
+    let cur_window = ws.get_current_window()?;
+
+    let main_mark = mark_name(marks, ws.name.as_ref().unwrap(), "main");
+    let last_mark = mark_name(marks, ws.name.as_ref().unwrap(), "last");
+
+    let main = ws.find_mark(&main_mark)?;
+
+    let other = if cur_window.id == main.id {
+        recent
+            .iter()
+            .find(|id| **id != main.id)
+            .copied()
+            .ok_or(NotFound::History)?
+    } else {
+        cur_window.id
+    };
+
+    Ok(vec![
+        format!("[con_id={}] swap container with con_id {}", main.id, other),
+        unmark(None, &last_mark),
+        unmark(None, &main_mark),
+        format!("[con_id={}] mark --add {}", other, main_mark),
+        mark(Some(main), &last_mark),
+    ])
+}
+
+fn focus_main(tree: &Node, marks: &str) -> Result<Vec<String>, NotFound> {
     //let node = conn.get_tree().map_err(|_| "get_tree 1")?;
     let ws = tree.get_current_workspace()?;
 
     Ok(vec![format!(
         "[con_mark={}] focus",
-        mark_name(ws.name.as_ref().unwrap(), "main")
+        mark_name(marks, ws.name.as_ref().unwrap(), "main")
     )])
 }
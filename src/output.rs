@@ -1,15 +1,16 @@
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use tokio_i3ipc::reply::{Outputs, Workspaces};
 
 use crate::ext::NotFound;
 
-#[derive(ValueEnum, Clone, Debug, Copy)]
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     Next,
     Prev,
 }
 
-#[derive(ValueEnum, Debug, Clone, Copy)]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Change {
     /// move workspace to a different output
     #[value(name = "move-ws")]
@@ -23,7 +24,7 @@ pub enum Change {
 }
 
 // TODO: clean me up
-fn neighbor(
+pub fn neighbor(
     which: Direction,
     workspaces: &Workspaces,
     outputs: &Outputs,
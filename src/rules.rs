@@ -0,0 +1,182 @@
+use anyhow::Context;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use tokio_i3ipc::{event::WindowChange, reply::Node};
+
+use crate::{
+    config::Config,
+    context::Ctx,
+    ext::{split_criteria_scopes, NodeExt},
+    send_commands, Cmd,
+};
+
+/// The rules the listener applies to windows, the `[[rule]]` tables in the config
+#[derive(Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Rules(Vec<Rule>);
+
+/// Which window events a rule is checked against
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    New,
+    Title,
+}
+
+/// Steps to run on windows that match all of the given properties. Properties that aren't given
+/// match anything.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default = "Rule::default_on")]
+    on: Vec<Trigger>,
+    class: Option<Pattern>,
+    instance: Option<Pattern>,
+    title: Option<Pattern>,
+    window_role: Option<Pattern>,
+    floating: Option<bool>,
+    /// Each step is either a plain i3 command, which is run on the matching window, or an
+    /// i3-valet action written like a binding (`nop loc rel se`), which is run with the matching
+    /// window focused.
+    run: Vec<String>,
+    #[serde(skip)]
+    steps: Vec<Cmd>,
+}
+
+/// A regex matched against a window property
+#[derive(Debug)]
+struct Pattern(Regex);
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        Regex::new(&s).map(Pattern).map_err(de::Error::custom)
+    }
+}
+
+impl Pattern {
+    fn matches(pat: &Option<Pattern>, value: Option<&String>) -> bool {
+        match pat {
+            None => true,
+            Some(Pattern(re)) => value.is_some_and(|v| re.is_match(v)),
+        }
+    }
+}
+
+impl Rules {
+    /// Parse the steps of every rule, so mistakes show up when the config is loaded rather than
+    /// when a window matches
+    pub fn prepare(&mut self) -> anyhow::Result<()> {
+        for (i, rule) in self.0.iter_mut().enumerate() {
+            rule.steps = rule
+                .run
+                .iter()
+                .enumerate()
+                .map(|(j, step)| {
+                    Cmd::parse(step).with_context(|| format!("rule[{}].run[{}]", i, j))
+                })
+                .collect::<anyhow::Result<_>>()?;
+        }
+        Ok(())
+    }
+
+    /// Check that every i3-valet action in the rules is a built-in action or an alias. Anything
+    /// not built in parses as an alias, so this can only be done once the aliases are loaded.
+    pub(crate) fn check_actions(&self, config: &Config) -> anyhow::Result<()> {
+        for (i, rule) in self.0.iter().enumerate() {
+            for (j, step) in rule.steps.iter().enumerate() {
+                if let Cmd::Valet(action) = step {
+                    config
+                        .check(action)
+                        .with_context(|| format!("rule[{}].run[{}]", i, j))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The rules that apply to a window event
+    pub fn matching<'a>(
+        &'a self,
+        change: WindowChange,
+        window: &'a Node,
+    ) -> impl Iterator<Item = &'a Rule> {
+        self.0.iter().filter(move |r| r.matches(change, window))
+    }
+}
+
+impl Rule {
+    fn default_on() -> Vec<Trigger> {
+        vec![Trigger::New]
+    }
+
+    fn matches(&self, change: WindowChange, window: &Node) -> bool {
+        let trigger = match change {
+            WindowChange::New => Trigger::New,
+            WindowChange::Title => Trigger::Title,
+            _ => return false,
+        };
+        let Some(props) = window.window_properties.as_ref() else {
+            return false;
+        };
+        self.on.contains(&trigger)
+            && Pattern::matches(&self.class, props.class.as_ref())
+            && Pattern::matches(&self.instance, props.instance.as_ref())
+            && Pattern::matches(&self.title, props.title.as_ref().or(window.name.as_ref()))
+            && Pattern::matches(&self.window_role, props.window_role.as_ref())
+            && self.floating.is_none_or(|f| f == window.is_floating())
+    }
+
+    pub(crate) fn steps(&self) -> &[Cmd] {
+        &self.steps
+    }
+}
+
+/// Point a plain i3 command at a window. Criteria only last until the next `;`, so each part of
+/// the command gets them, except parts that pick their own windows.
+fn on_window(window: usize, cmd: &str) -> String {
+    let parts: Vec<String> = split_criteria_scopes(cmd)
+        .into_iter()
+        .map(str::trim)
+        .map(|part| match part.starts_with('[') {
+            true => part.to_string(),
+            false => format!("[con_id={}] {}", window, part),
+        })
+        .collect();
+    parts.join("; ")
+}
+
+/// Run a rule's steps on a window. i3-valet actions work on the focused window, so the window is
+/// focused before each of them.
+pub(crate) async fn run(ctx: &mut Ctx<'_>, window: usize, steps: &[Cmd]) -> anyhow::Result<()> {
+    for step in steps {
+        match step {
+            Cmd::I3(cmd) => send_commands(ctx, &[on_window(window, cmd)]).await?,
+            Cmd::Valet(action) => {
+                send_commands(ctx, &[format!("[con_id={}] focus", window)]).await?;
+                action.dispatch(ctx).await?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_part_of_a_command_is_on_the_window() {
+        assert_eq!(
+            on_window(
+                7,
+                "floating enable, resize set 640 480; move position center"
+            ),
+            "[con_id=7] floating enable, resize set 640 480; [con_id=7] move position center"
+        );
+        assert_eq!(
+            on_window(7, r#"mark "a;b"; [class="x"] kill"#),
+            r#"[con_id=7] mark "a;b"; [class="x"] kill"#
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio_i3ipc::reply::Node;
+
+use crate::{
+    context::Ctx,
+    ext::{NodeExt, NodeSearch},
+    parse_action_string, Action,
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Test {
+    /// the focused window is floating
+    Floating,
+    /// the focused window's container has the given layout: splith, splitv, stacked or tabbed
+    Layout,
+    /// the focused window's class matches the given regex
+    Class,
+    /// the focused window has the given mark
+    Mark,
+    /// the focused workspace has the given name
+    Workspace,
+}
+
+/// Check a test against the focused window. `value` is what the test compares against, which
+/// every test but `floating` needs.
+pub fn check(test: Test, value: Option<&str>, tree: &Node) -> anyhow::Result<bool> {
+    let window = tree.get_current_window()?;
+    let value = || value.ok_or_else(|| anyhow!("This test needs a value to compare against"));
+    Ok(match test {
+        Test::Floating => window.is_floating(),
+        Test::Layout => {
+            let parent = tree
+                .search_focus_path(|n| n.nodes.iter().any(|c| c.id == window.id))
+                .unwrap_or(window);
+            serde_json::to_value(parent.layout)?.as_str() == Some(value()?)
+        }
+        Test::Class => {
+            let re = Regex::new(value()?).context("when class")?;
+            let class = window
+                .window_properties
+                .as_ref()
+                .and_then(|p| p.class.as_ref());
+            class.is_some_and(|c| re.is_match(c))
+        }
+        Test::Mark => {
+            let mark = value()?;
+            window
+                .marks
+                .as_ref()
+                .is_some_and(|m| m.0.iter().any(|m| m == mark))
+        }
+        Test::Workspace => tree.get_current_workspace()?.name.as_deref() == Some(value()?),
+    })
+}
+
+/// Pick the action to run, from the action strings given for when the test passes and when it
+/// doesn't. Returns `None` if no action was given for the way the test came out.
+pub(crate) async fn choose(
+    ctx: &mut Ctx<'_>,
+    test: Test,
+    value: Option<&str>,
+    then: Option<&str>,
+    otherwise: Option<&str>,
+) -> anyhow::Result<Option<Action>> {
+    // both are checked, so a mistake shows up whichever way the test comes out
+    let parse = |branch: Option<&str>| -> anyhow::Result<Option<Action>> {
+        let Some(branch) = branch else {
+            return Ok(None);
+        };
+        let action = parse_action_string(branch)?.action;
+        ctx.config.check(&action)?;
+        Ok(Some(action))
+    };
+    let then = parse(then).context("--then")?;
+    let otherwise = parse(otherwise).context("--else")?;
+
+    // i3 sends no event when a container's layout is changed over IPC, so the cache can be behind
+    let tree = ctx.fresh_tree().await.context("Get tree for When")?;
+    Ok(match check(test, value, &tree)? {
+        true => then,
+        false => otherwise,
+    })
+}
@@ -1,35 +1,41 @@
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use tokio_i3ipc::reply::Workspaces;
 
-#[derive(ValueEnum, Clone, Debug, Copy)]
+#[derive(ValueEnum, Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WorkspaceTarget {
     /// Create and focus a new workspace
     Alloc,
     /// Create a new workspace and move the current focused container to it.
     MoveNew,
+    /// Create a new workspace and move the current focused container to it, without following
+    /// it there.
+    SendNew,
 }
 
-pub fn run(target: WorkspaceTarget, workspaces: &mut Workspaces) -> Vec<String> {
-    let ws = next_free_workspace(workspaces);
+pub fn run(target: WorkspaceTarget, workspaces: &mut Workspaces, first: i32) -> Vec<String> {
+    let ws = next_free_workspace(workspaces, first);
     let cmd = match target {
         WorkspaceTarget::Alloc => format!("workspace {}", ws),
         WorkspaceTarget::MoveNew => format!("move container to workspace {}; workspace {}", ws, ws),
+        WorkspaceTarget::SendNew => format!("move container to workspace {}", ws),
     };
     vec![cmd]
 }
 
-pub fn next_free_workspace(workspaces: &mut Workspaces) -> i32 {
+/// The lowest numbered workspace, starting from `first`, that doesn't exist
+pub fn next_free_workspace(workspaces: &mut Workspaces, first: i32) -> i32 {
     workspaces.sort_by(|a, b| a.num.partial_cmp(&b.num).unwrap());
 
-    // go over the workspaces that are present.
-    // Workspace 0 doesn't exist, and named workspaces are num = -1
-    // After ws 1, any gap we find (where cur.num - prev > 1)
+    // go over the workspaces that are present, from the first one we
+    // may use. Named workspaces are num = -1
+    // After that, any gap we find (where cur.num - prev > 1)
     // we'll break the search, leaving prev set to the last ws before
     // gap. If there's no gaps, we'll fall off the end, with prev being
     // the highest seen ws num. In both cases, adding 1 gets us what we
     // want.
-    let mut prev = 0;
-    for ws in workspaces.iter().skip_while(|x| x.num < 1) {
+    let mut prev = first - 1;
+    for ws in workspaces.iter().skip_while(|x| x.num < first) {
         if ws.num - prev > 1 {
             break;
         }